@@ -0,0 +1,109 @@
+use crate::{CompileCommand, CompileDbError};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// One entry of a bear-format database. `cmd` takes the place of both
+/// `command` and `arguments`.
+#[derive(Debug, Serialize, Deserialize)]
+struct BearEntry {
+    directory: String,
+    file: String,
+    cmd: BearCmd,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+}
+
+/// `cmd` is written as an argument list; a shell string is accepted on import
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum BearCmd {
+    Arguments(Vec<String>),
+    Command(String),
+}
+
+/// Convert a database to the bear schema: an array of
+/// `{"directory", "file", "cmd": [...], "output"}` objects
+pub fn bear_convert(commands: &[CompileCommand]) -> serde_json::Value {
+    let entries: Vec<BearEntry> = commands
+        .iter()
+        .map(|cmd| BearEntry {
+            directory: cmd.directory.clone(),
+            file: cmd.file.clone(),
+            cmd: BearCmd::Arguments(cmd.to_arguments_style().arguments.unwrap_or_default()),
+            output: cmd.output.clone(),
+        })
+        .collect();
+    serde_json::to_value(entries).unwrap_or_default()
+}
+
+/// Read a bear-format database written by [`bear_convert`] or bear itself.
+/// Entries keep the arguments form unless `cmd` is a shell string.
+pub fn bear_import(path: &Path) -> Result<Vec<CompileCommand>, CompileDbError> {
+    let contents = std::fs::read_to_string(path)?;
+    let entries: Vec<BearEntry> = serde_json::from_str(&contents)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            let (command, arguments) = match entry.cmd {
+                BearCmd::Arguments(arguments) => (None, Some(arguments)),
+                BearCmd::Command(command) => (Some(command), None),
+            };
+            CompileCommand {
+                directory: entry.directory,
+                file: entry.file,
+                command,
+                arguments,
+                output: entry.output,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bear_round_trip() {
+        let commands = vec![
+            CompileCommand {
+                directory: String::from("/src"),
+                file: String::from("a.c"),
+                command: Some(String::from("gcc '-DMSG=a b' -c a.c -o a.o")),
+                arguments: None,
+                output: Some(String::from("a.o")),
+            },
+            CompileCommand {
+                directory: String::from("/src"),
+                file: String::from("b.c"),
+                command: None,
+                arguments: Some(["cc", "-c", "b.c"].map(String::from).to_vec()),
+                output: None,
+            },
+        ];
+
+        let bear = bear_convert(&commands);
+        assert_eq!(
+            bear[0]["cmd"],
+            serde_json::json!(["gcc", "-DMSG=a b", "-c", "a.c", "-o", "a.o"])
+        );
+        assert_eq!(bear[0]["output"], "a.o");
+        assert!(bear[0].get("command").is_none());
+        assert!(bear[1].get("output").is_none());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bear.json");
+        std::fs::write(&path, bear.to_string()).unwrap();
+        let imported = bear_import(&path).unwrap();
+        assert_eq!(imported[0], commands[0].to_arguments_style());
+        assert_eq!(imported[1], commands[1]);
+
+        std::fs::write(
+            &path,
+            r#"[{"directory": "/src", "file": "c.c", "cmd": "gcc -c c.c"}]"#,
+        )
+        .unwrap();
+        let imported = bear_import(&path).unwrap();
+        assert_eq!(imported[0].command.as_deref(), Some("gcc -c c.c"));
+    }
+}
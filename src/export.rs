@@ -0,0 +1,117 @@
+use crate::{CompileCommand, CompileDbError};
+use std::io::Write;
+use std::path::Path;
+use tracing::warn;
+
+/// Write a Makefile that rebuilds every object file recorded in `commands`.
+///
+/// Each entry becomes a rule `<output>: <file>` whose recipe changes into the
+/// entry's `directory` and runs the recorded command. The output comes from
+/// the `output` field or the command's `-o` argument; entries with neither
+/// are skipped with a warning. An `all` target depends on every output.
+pub fn export_makefile(commands: &[CompileCommand], path: &Path) -> Result<(), CompileDbError> {
+    let mut rules = Vec::new();
+    for cmd in commands {
+        let Some(output) = output_of(cmd) else {
+            warn!("No output recorded for {}, skipping it", cmd.file);
+            continue;
+        };
+        let directory = Path::new(&cmd.directory);
+        let Some(command) = cmd.to_command_style().command else {
+            continue;
+        };
+        rules.push((
+            directory.join(output),
+            directory.join(&cmd.file),
+            &cmd.directory,
+            command,
+        ));
+    }
+
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(out, "# Generated by compiledb from a compilation database")?;
+    writeln!(out, ".PHONY: all")?;
+    write!(out, "all:")?;
+    for (output, ..) in &rules {
+        write!(out, " {}", escape_path(output))?;
+    }
+    writeln!(out)?;
+
+    for (output, source, directory, command) in &rules {
+        let directory = shlex::try_quote(directory)
+            .map_or_else(|_| directory.to_string(), |dir| dir.into_owned());
+        writeln!(out)?;
+        writeln!(out, "{}: {}", escape_path(output), escape_path(source))?;
+        writeln!(out, "\tcd {directory} && {}", command.replace('$', "$$"))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// The object file an entry produces, from `output` or its `-o` argument
+fn output_of(cmd: &CompileCommand) -> Option<String> {
+    if let Some(output) = &cmd.output {
+        return Some(output.clone());
+    }
+    let arguments = cmd.to_arguments_style().arguments?;
+    let index = arguments.iter().position(|arg| arg == "-o")?;
+    arguments.get(index + 1).cloned()
+}
+
+/// Escape a path for use as a make target or prerequisite
+fn escape_path(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('$', "$$")
+        .replace('#', "\\#")
+        .replace(' ', "\\ ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_makefile() {
+        let dir = tempfile::tempdir().unwrap();
+        let commands = vec![
+            CompileCommand {
+                directory: String::from("/src/lib"),
+                file: String::from("util.c"),
+                command: None,
+                arguments: Some(
+                    ["gcc", "-DNAME=$x", "-c", "util.c", "-o", "util.o"]
+                        .map(String::from)
+                        .to_vec(),
+                ),
+                output: None,
+            },
+            CompileCommand {
+                directory: String::from("/src/my app"),
+                file: String::from("main.c"),
+                command: Some(String::from("cc -c main.c")),
+                arguments: None,
+                output: Some(String::from("main.o")),
+            },
+            CompileCommand {
+                directory: String::from("/src"),
+                file: String::from("orphan.c"),
+                command: Some(String::from("cc -c orphan.c")),
+                arguments: None,
+                output: None,
+            },
+        ];
+
+        let path = dir.path().join("compile_commands.mk");
+        export_makefile(&commands, &path).unwrap();
+        let makefile = std::fs::read_to_string(&path).unwrap();
+
+        assert!(makefile.contains("all: /src/lib/util.o /src/my\\ app/main.o\n"));
+        assert!(makefile.contains(
+            "/src/lib/util.o: /src/lib/util.c\n\tcd /src/lib && gcc '-DNAME=$$x' -c util.c -o util.o\n"
+        ));
+        assert!(makefile.contains(
+            "/src/my\\ app/main.o: /src/my\\ app/main.c\n\tcd '/src/my app' && cc -c main.c\n"
+        ));
+        assert!(!makefile.contains("orphan"));
+    }
+}
@@ -1,9 +1,21 @@
+use indexmap::IndexMap;
+use rustc_hash::{FxBuildHasher, FxHashSet};
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::hash::BuildHasher;
+use std::path::{Component, Path, PathBuf};
 use thiserror::Error;
 
+pub mod bear;
+pub mod export;
+pub mod lint;
 pub mod make_wrapper;
+pub mod meson_importer;
 pub mod parser;
+pub mod response_file;
+pub mod scons_parser;
+pub mod tup_parser;
+pub mod writer;
+pub mod xcode_parser;
 
 #[derive(Debug, Error)]
 pub enum CompileDbError {
@@ -18,10 +30,54 @@ pub enum CompileDbError {
 
     #[error("Make execution failed: {0}")]
     MakeError(String),
+
+    /// Make ran but exited unsuccessfully, in the dry run or the real build;
+    /// `stderr_tail` holds its last lines of stderr (see
+    /// `Config::error_context_lines`). Failures to run make at all are
+    /// [`MakeError`](Self::MakeError)
+    #[error("Build failed with {}{}", describe_exit(.exit_code), describe_tail(.stderr_tail))]
+    BuildFailed {
+        /// Exit code of make, `None` if it was killed by a signal
+        exit_code: Option<i32>,
+        stderr_tail: Vec<String>,
+    },
+
+    #[error("Parse failure at line {line}: {reason}: {content}")]
+    ParseFailure {
+        line: usize,
+        content: String,
+        reason: String,
+    },
+
+    #[error("TOML error: {0}")]
+    TomlError(String),
+
+    #[error("Lint found {0} issues")]
+    LintFailed(usize),
+
+    #[error("Found {found} compile commands, expected at least {expected}")]
+    TooFewCommands { found: usize, expected: usize },
+
+    #[error("Invalid entry {index}: {reason}")]
+    InvalidEntry { index: usize, reason: String },
+}
+
+fn describe_exit(exit_code: &Option<i32>) -> String {
+    match exit_code {
+        Some(code) => format!("exit code {code}"),
+        None => String::from("a signal"),
+    }
+}
+
+fn describe_tail(stderr_tail: &[String]) -> String {
+    if stderr_tail.is_empty() {
+        return String::new();
+    }
+    format!(", last lines of stderr:\n{}", stderr_tail.join("\n"))
 }
 
 /// Represents a single compilation command in the database
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CompileCommand {
     /// The working directory for the compilation
     pub directory: String,
@@ -29,80 +85,1609 @@ pub struct CompileCommand {
     /// The main source file to compile
     pub file: String,
 
-    /// The command as a single string (when command_style is true)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub command: Option<String>,
+    /// The command as a single string (when command_style is true)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+
+    /// The command as a list of arguments (when command_style is false)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Vec<String>>,
+
+    /// Optional output file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// Source language of a translation unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    C,
+    Cpp,
+    Asm,
+    ObjC,
+    Cuda,
+}
+
+impl Language {
+    /// Classify a source file, preferring the last `-x <language>` in `args`
+    /// over the file extension
+    pub fn detect<S: AsRef<str>>(file: &str, args: &[S]) -> Option<Language> {
+        let mut x_flag = None;
+        let mut args = args.iter().map(AsRef::as_ref);
+        while let Some(arg) = args.next() {
+            if arg == "-x" {
+                x_flag = args.next();
+            } else if let Some(value) = arg.strip_prefix("-x") {
+                x_flag = Some(value);
+            }
+        }
+
+        match x_flag.and_then(Self::from_x_flag) {
+            Some(language) => Some(language),
+            None => Self::from_extension(Path::new(file).extension()?.to_str()?),
+        }
+    }
+
+    /// Language named by a GCC/Clang `-x` value; `none` and unknown values give `None`
+    fn from_x_flag(value: &str) -> Option<Language> {
+        match value {
+            "c" | "c-header" => Some(Language::C),
+            "c++" | "c++-header" => Some(Language::Cpp),
+            "assembler" | "assembler-with-cpp" => Some(Language::Asm),
+            "objective-c" | "objective-c++" | "objective-c-header" | "objective-c++-header" => {
+                Some(Language::ObjC)
+            }
+            "cuda" => Some(Language::Cuda),
+            _ => None,
+        }
+    }
+
+    fn from_extension(extension: &str) -> Option<Language> {
+        match extension {
+            "c" => Some(Language::C),
+            "cc" | "cp" | "cpp" | "cxx" | "c++" | "C" | "CPP" | "ixx" | "cppm" => {
+                Some(Language::Cpp)
+            }
+            "s" | "S" | "asm" => Some(Language::Asm),
+            "m" | "mm" => Some(Language::ObjC),
+            "cu" => Some(Language::Cuda),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c" => Ok(Language::C),
+            "cpp" | "c++" => Ok(Language::Cpp),
+            "asm" => Ok(Language::Asm),
+            "objc" => Ok(Language::ObjC),
+            "cuda" => Ok(Language::Cuda),
+            _ => Err(format!(
+                "unknown language '{s}' (expected c, cpp, asm, objc or cuda)"
+            )),
+        }
+    }
+}
+
+/// File format of a written compilation database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// A pretty-printed JSON array, the format tools expect
+    #[default]
+    Json,
+
+    /// One compact JSON object per line, which can be appended to
+    Jsonl,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            _ => Err(format!("unknown format '{s}' (expected json or jsonl)")),
+        }
+    }
+}
+
+/// Build system that produced a log read with `--parse` or from stdin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `make` output, or any log that echoes plain compiler command lines
+    #[default]
+    Make,
+
+    /// `xcodebuild -verbose` output, see [`xcode_parser::XcodeParser`]
+    Xcode,
+
+    /// `tup monitor --show-commands` output, see [`tup_parser::TupParser`]
+    Tup,
+
+    /// SCons output, see [`scons_parser::SconsParser`]
+    Scons,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "make" => Ok(LogFormat::Make),
+            "xcode" => Ok(LogFormat::Xcode),
+            "tup" => Ok(LogFormat::Tup),
+            "scons" => Ok(LogFormat::Scons),
+            _ => Err(format!(
+                "unknown log format '{s}' (expected make, xcode, tup or scons)"
+            )),
+        }
+    }
+}
+
+/// Which entry to keep when a translation unit appears more than once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeStrategy {
+    /// The first entry for the unit
+    #[serde(rename = "first")]
+    KeepFirst,
+
+    /// The last entry for the unit
+    #[serde(rename = "last")]
+    KeepLast,
+
+    /// The entry with the most arguments (or the longest command), assuming
+    /// more flags means a more complete compilation
+    #[serde(rename = "most-flags")]
+    KeepMostFlags,
+}
+
+impl std::str::FromStr for MergeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(MergeStrategy::KeepFirst),
+            "last" => Ok(MergeStrategy::KeepLast),
+            "most-flags" => Ok(MergeStrategy::KeepMostFlags),
+            _ => Err(format!(
+                "unknown strategy '{s}' (expected first, last or most-flags)"
+            )),
+        }
+    }
+}
+
+/// Builder for [`CompileCommand`]; [`build`](Self::build) checks the entry
+/// with [`CompileCommand::validate`]
+#[derive(Debug, Clone, Default)]
+pub struct CompileCommandBuilder {
+    directory: String,
+    file: String,
+    command: Option<String>,
+    arguments: Option<Vec<String>>,
+    output: Option<String>,
+}
+
+impl CompileCommandBuilder {
+    pub fn directory(mut self, directory: impl Into<String>) -> Self {
+        self.directory = directory.into();
+        self
+    }
+
+    pub fn file(mut self, file: impl Into<String>) -> Self {
+        self.file = file.into();
+        self
+    }
+
+    pub fn command(mut self, command: impl Into<String>) -> Self {
+        self.command = Some(command.into());
+        self
+    }
+
+    pub fn arguments<I, S>(mut self, arguments: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.arguments = Some(arguments.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn output(mut self, output: impl Into<String>) -> Self {
+        self.output = Some(output.into());
+        self
+    }
+
+    /// Finish the entry, failing unless `directory` and `file` are non-empty
+    /// and exactly one of `command`/`arguments` is set
+    pub fn build(self) -> Result<CompileCommand, CompileDbError> {
+        let cmd = CompileCommand {
+            directory: self.directory,
+            file: self.file,
+            command: self.command,
+            arguments: self.arguments,
+            output: self.output,
+        };
+        cmd.validate()?;
+        Ok(cmd)
+    }
+}
+
+impl CompileCommand {
+    /// Start building an entry, see [`CompileCommandBuilder`]
+    pub fn builder() -> CompileCommandBuilder {
+        CompileCommandBuilder::default()
+    }
+
+    /// Source language of this entry, see [`Language::detect`]
+    pub fn language(&self) -> Option<Language> {
+        match (&self.arguments, &self.command) {
+            (Some(arguments), _) => Language::detect(&self.file, arguments),
+            (None, Some(command)) => {
+                Language::detect(&self.file, &shell_words(command).collect::<Vec<_>>())
+            }
+            (None, None) => Language::detect::<&str>(&self.file, &[]),
+        }
+    }
+
+    /// Check the entry against the compilation database spec: `directory` and
+    /// `file` are non-empty and exactly one of `command`/`arguments` is set
+    pub fn validate(&self) -> Result<(), CompileDbError> {
+        match self.validation_error() {
+            Some(reason) => Err(CompileDbError::InvalidCommand(reason.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn validation_error(&self) -> Option<&'static str> {
+        if self.directory.is_empty() {
+            return Some("empty directory");
+        }
+        if self.file.is_empty() {
+            return Some("empty file");
+        }
+        match (&self.command, &self.arguments) {
+            (Some(_), Some(_)) => Some("both command and arguments are set"),
+            (None, None) => Some("neither command nor arguments is set"),
+            _ => None,
+        }
+    }
+
+    /// The compiler of this entry: `arguments[0]`, or the first word of `command`
+    /// after any `NAME=VALUE` assignments
+    pub fn compiler(&self) -> Option<&str> {
+        match (&self.arguments, &self.command) {
+            (Some(arguments), _) => arguments.first().map(String::as_str),
+            (None, Some(command)) => {
+                shell_words(command).find(|word| !parser::is_env_assignment(word))
+            }
+            (None, None) => None,
+        }
+    }
+
+    /// Index in `arguments` of the source file following `-c`
+    pub fn source_file_arg_index(&self) -> Option<usize> {
+        let arguments = self.arguments.as_ref()?;
+        let index = arguments.iter().position(|arg| arg == "-c")? + 1;
+        (index < arguments.len()).then_some(index)
+    }
+
+    /// Directories added by `-I`, `-isystem` and `-iquote`, resolved against
+    /// `directory`, in command-line order
+    pub fn include_dirs(&self) -> Vec<PathBuf> {
+        let arguments = self.to_arguments_style().arguments.unwrap_or_default();
+        let mut dirs = Vec::new();
+        let mut args = arguments.iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = ["-isystem", "-iquote", "-I"]
+                .into_iter()
+                .find(|flag| arg.starts_with(flag))
+            else {
+                continue;
+            };
+            let dir = if arg == flag {
+                match args.next() {
+                    Some(dir) => dir.as_str(),
+                    None => break,
+                }
+            } else {
+                &arg[flag.len()..]
+            };
+            dirs.push(normalize_path(&Path::new(&self.directory).join(dir)));
+        }
+        dirs
+    }
+
+    /// Remove dependency-generation flags (`-MD`, `-MMD`, `-MF <file>`, ...) in
+    /// place. A `command` is shell-split and re-quoted, so quoted arguments
+    /// survive.
+    pub fn strip_dependency_flags(&mut self) {
+        if let Some(arguments) = self.arguments.as_mut() {
+            *arguments = strip_dependency_args(arguments.iter().map(String::as_str));
+        }
+        if let Some(command) = self.command.as_mut() {
+            let words = split_arguments(command);
+            let stripped = strip_dependency_args(words.iter().map(String::as_str));
+            *command = join_arguments(&stripped);
+        }
+    }
+
+    /// Return a copy of this entry with `flags` appended to its arguments or command
+    pub fn with_extra_flags(&self, flags: &[&str]) -> CompileCommand {
+        let mut cmd = self.clone();
+        if let Some(arguments) = cmd.arguments.as_mut() {
+            arguments.extend(flags.iter().map(|flag| flag.to_string()));
+        }
+        if let Some(command) = cmd.command.as_mut() {
+            for flag in flags {
+                command.push(' ');
+                command.push_str(flag);
+            }
+        }
+        cmd
+    }
+
+    /// Return a copy of this entry with `-I<dir>` for each of `dirs` inserted
+    /// before its first include flag, or right after the compiler if it has none
+    pub fn with_prepended_includes(&self, dirs: &[PathBuf]) -> CompileCommand {
+        let flags: Vec<String> = dirs
+            .iter()
+            .map(|dir| format!("-I{}", dir.display()))
+            .collect();
+        let insert_at = |words: &[&str], compiler: usize| {
+            words
+                .iter()
+                .position(|word| is_include_flag(word))
+                .unwrap_or((compiler + 1).min(words.len()))
+        };
+
+        let mut cmd = self.clone();
+        if let Some(arguments) = cmd.arguments.as_mut() {
+            let words: Vec<&str> = arguments.iter().map(String::as_str).collect();
+            let index = insert_at(&words, 0);
+            arguments.splice(index..index, flags.iter().cloned());
+        }
+        if let Some(command) = cmd.command.as_mut() {
+            let mut words: Vec<&str> = shell_words(command).collect();
+            let compiler = words
+                .iter()
+                .position(|word| !parser::is_env_assignment(word))
+                .unwrap_or(0);
+            let index = insert_at(&words, compiler);
+            let quoted: Vec<String> = flags
+                .iter()
+                .map(|flag| shlex::try_quote(flag).map_or_else(|_| flag.clone(), Into::into))
+                .collect();
+            words.splice(index..index, quoted.iter().map(String::as_str));
+            *command = words.join(" ");
+        }
+        cmd
+    }
+
+    /// Return a copy of this entry with its compiler (see [`compiler`](Self::compiler))
+    /// replaced by `new_compiler`, leaving every other flag untouched
+    pub fn replace_compiler(&self, new_compiler: &str) -> CompileCommand {
+        let mut cmd = self.clone();
+        if let Some(compiler) = cmd.arguments.as_mut().and_then(|args| args.first_mut()) {
+            *compiler = new_compiler.to_string();
+        }
+        if let Some(command) = cmd.command.as_mut() {
+            let mut words: Vec<&str> = shell_words(command).collect();
+            let quoted =
+                shlex::try_quote(new_compiler).unwrap_or(std::borrow::Cow::Borrowed(new_compiler));
+            if let Some(word) = words
+                .iter_mut()
+                .find(|word| !parser::is_env_assignment(word))
+            {
+                *word = &quoted;
+            }
+            *command = words.join(" ");
+        }
+        cmd
+    }
+
+    /// Return a copy of this entry with its compiler reduced to the bare
+    /// compiler name, e.g. `/opt/bin/arm-none-eabi-gcc-12` becomes `gcc`.
+    /// Unknown compilers only lose their directory.
+    pub fn with_normalized_compiler(&self) -> CompileCommand {
+        match self.compiler() {
+            Some(compiler) => self.replace_compiler(&normalize_compiler_name(compiler)),
+            None => self.clone(),
+        }
+    }
+
+    /// Replace `\` with `/` in `directory`, `file`, `output` and the arguments
+    /// that are Windows paths, i.e. start with a drive letter (`C:`) or `\\`
+    pub fn normalize_path_separators(&mut self) {
+        self.directory = self.directory.replace('\\', "/");
+        self.file = self.file.replace('\\', "/");
+        if let Some(output) = self.output.as_mut() {
+            *output = output.replace('\\', "/");
+        }
+        if let Some(arguments) = self.arguments.as_mut() {
+            for arg in arguments.iter_mut().filter(|arg| is_windows_path(arg)) {
+                *arg = arg.replace('\\', "/");
+            }
+        }
+        if let Some(command) = self.command.as_mut() {
+            let mut words = split_arguments(command);
+            for word in words.iter_mut().filter(|word| is_windows_path(word)) {
+                *word = word.replace('\\', "/");
+            }
+            *command = join_arguments(&words);
+        }
+    }
+
+    /// Return a copy of this entry with the extension of its `output` and of
+    /// the `-o` argument rewritten by `map`, a list of `(from, to)` pairs such
+    /// as `("o", "obj")`
+    pub fn with_output_extensions(&self, map: &[(String, String)]) -> CompileCommand {
+        let mut cmd = self.clone();
+        if let Some(output) = cmd.output.as_mut() {
+            if let Some(mapped) = map_extension(output, map) {
+                *output = mapped;
+            }
+        }
+        if let Some(arguments) = cmd.arguments.as_mut() {
+            if let Some(index) = arguments.iter().position(|arg| arg == "-o") {
+                if let Some(mapped) = arguments.get(index + 1).and_then(|o| map_extension(o, map)) {
+                    arguments[index + 1] = mapped;
+                }
+            }
+        }
+        if let Some(command) = cmd.command.as_mut() {
+            let mut words: Vec<String> = shell_words(command).map(String::from).collect();
+            if let Some(index) = words.iter().position(|word| word == "-o") {
+                if let Some(mapped) = words.get(index + 1).and_then(|o| map_extension(o, map)) {
+                    words[index + 1] = mapped;
+                    *command = words.join(" ");
+                }
+            }
+        }
+        cmd
+    }
+
+    /// Return a copy of this entry using `arguments`, shell-splitting `command`
+    /// if that is the only form present
+    pub fn to_arguments_style(&self) -> CompileCommand {
+        let mut cmd = self.clone();
+        if cmd.arguments.is_none() {
+            cmd.arguments = cmd.command.as_deref().map(split_arguments);
+        }
+        if cmd.arguments.is_some() {
+            cmd.command = None;
+        }
+        cmd
+    }
+
+    /// Return a copy of this entry using `command`, joining `arguments` with
+    /// shell quoting if that is the only form present
+    pub fn to_command_style(&self) -> CompileCommand {
+        let mut cmd = self.clone();
+        if cmd.command.is_none() {
+            cmd.command = cmd.arguments.as_ref().map(|arguments| {
+                shlex::try_join(arguments.iter().map(String::as_str))
+                    .unwrap_or_else(|_| arguments.join(" "))
+            });
+        }
+        if cmd.command.is_some() {
+            cmd.arguments = None;
+        }
+        cmd
+    }
+}
+
+/// Whether `arg` is a Windows path: it starts with a drive letter (`C:`) or `\\`
+fn is_windows_path(arg: &str) -> bool {
+    let bytes = arg.as_bytes();
+    arg.starts_with("\\\\")
+        || (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+}
+
+/// Whether `arg` adds an include directory (`-I`, `-isystem`)
+fn is_include_flag(arg: &str) -> bool {
+    arg.starts_with("-I") || arg.starts_with("-isystem")
+}
+
+/// Split `command` on unquoted whitespace, keeping the quotes in each word
+fn shell_words(command: &str) -> impl Iterator<Item = &str> {
+    let mut rest = command.trim_start();
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let mut quote = None;
+        let mut escaped = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                match (quote, c) {
+                    _ if escaped => escaped = false,
+                    (Some('\''), '\'') => quote = None,
+                    (Some('\''), _) => {}
+                    (_, '\\') => escaped = true,
+                    (Some('"'), '"') => quote = None,
+                    (None, '\'' | '"') => quote = Some(c),
+                    (None, c) if c.is_whitespace() => return true,
+                    _ => {}
+                }
+                false
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let (word, tail) = rest.split_at(end);
+        rest = tail.trim_start();
+        Some(word)
+    })
+}
+
+/// Split a command line on unquoted whitespace, removing `'`/`"` quotes so
+/// `-DMSG="a b"` stays one argument. Unlike a POSIX shell, an unquoted
+/// backslash is literal unless it escapes a quote, so Windows paths like
+/// `C:\src\a.c` survive; inside double quotes it escapes as usual. Falls back to whitespace splitting on unbalanced quotes.
+pub(crate) fn split_arguments(command: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => current.push(c),
+            (Some(_), '\\') if matches!(chars.peek(), Some('"' | '\\' | '$' | '`')) => {
+                current.extend(chars.next());
+            }
+            (None, '\\') if matches!(chars.peek(), Some('"' | '\'')) => {
+                current.extend(chars.next());
+                in_word = true;
+            }
+            (Some(_), '"') => quote = None,
+            (Some(_), _) => current.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    args.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, _) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return command.split_whitespace().map(String::from).collect();
+    }
+    if in_word {
+        args.push(current);
+    }
+    args
+}
+
+/// Join arguments into a command line with shell quoting where needed
+pub(crate) fn join_arguments(arguments: &[String]) -> String {
+    shlex::try_join(arguments.iter().map(String::as_str)).unwrap_or_else(|_| arguments.join(" "))
+}
+
+/// Append `flags` to every command in the database
+pub fn inject_flags(commands: &mut [CompileCommand], flags: &[&str]) {
+    for cmd in commands.iter_mut() {
+        *cmd = cmd.with_extra_flags(flags);
+    }
+}
+
+/// Prefix every command-style entry with `NAME=VALUE` assignments. The
+/// `arguments` form cannot express environment variables, so those entries
+/// are left unchanged.
+pub fn prepend_env_vars(commands: &mut [CompileCommand], env_vars: &[(String, String)]) {
+    if env_vars.is_empty() {
+        return;
+    }
+    let prefix: String = env_vars
+        .iter()
+        .map(|(name, value)| {
+            let value = shlex::try_quote(value).unwrap_or(std::borrow::Cow::Borrowed(value));
+            format!("{name}={value} ")
+        })
+        .collect();
+    for cmd in commands.iter_mut() {
+        if let Some(command) = cmd.command.as_mut() {
+            command.insert_str(0, &prefix);
+        }
+    }
+}
+
+/// Replace the compiler of every command in the database with `new_compiler`
+pub fn replace_compiler_all(commands: &mut [CompileCommand], new_compiler: &str) {
+    for cmd in commands.iter_mut() {
+        *cmd = cmd.replace_compiler(new_compiler);
+    }
+}
+
+/// Normalize the compiler of every command in the database, see
+/// [`CompileCommand::with_normalized_compiler`]
+pub fn normalize_compilers(commands: &mut [CompileCommand]) {
+    for cmd in commands.iter_mut() {
+        *cmd = cmd.with_normalized_compiler();
+    }
+}
+
+/// Strip the directory, target triple, version suffix and `.exe` from a
+/// known compiler name
+fn normalize_compiler_name(compiler: &str) -> String {
+    static NAME_REGEX: std::sync::LazyLock<regex::Regex> = std::sync::LazyLock::new(|| {
+        regex::Regex::new(
+            r"^(?:.*?-)??(gcc|clang|clang-cl|clang-cpp|clang-cc1|cc|g\+\+|c\+\+|clang\+\+|cl|gfortran|ifort|ifx|pgfortran|nvfortran|flang|flang-new)(?:-[0-9.]+)?(?:\.exe)?$",
+        )
+        .unwrap()
+    });
+
+    let name = compiler.rsplit(['/', '\\']).next().unwrap_or(compiler);
+    match NAME_REGEX.captures(name) {
+        Some(caps) => caps[1].to_string(),
+        None => name.to_string(),
+    }
+}
+
+/// Rewrite the output extension of every command in the database, see
+/// [`CompileCommand::with_output_extensions`]
+pub fn map_output_extensions(commands: &mut [CompileCommand], map: &[(String, String)]) {
+    for cmd in commands.iter_mut() {
+        *cmd = cmd.with_output_extensions(map);
+    }
+}
+
+/// Replace the extension of `path` using the first matching `(from, to)` pair.
+/// Extensions may be given with or without the leading dot.
+fn map_extension(path: &str, map: &[(String, String)]) -> Option<String> {
+    map.iter().find_map(|(from, to)| {
+        let stem = path.strip_suffix(from.trim_start_matches('.'))?;
+        stem.ends_with('.')
+            .then(|| format!("{stem}{}", to.trim_start_matches('.')))
+    })
+}
+
+/// Remove dependency-generation flags from every command in the database
+pub fn strip_dependency_flags_all(commands: &mut [CompileCommand]) {
+    for cmd in commands.iter_mut() {
+        cmd.strip_dependency_flags();
+    }
+}
+
+pub(crate) fn strip_dependency_args<'a>(args: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        match arg {
+            "-MD" | "-MMD" | "-MG" | "-MP" => {}
+            // These take the next argument as their value
+            "-MF" | "-MT" | "-MQ" => {
+                args.next();
+            }
+            // Joined form, e.g. -MFdeps.d
+            _ if arg.starts_with("-MF") || arg.starts_with("-MT") || arg.starts_with("-MQ") => {}
+            _ => result.push(arg.to_string()),
+        }
+    }
+    result
+}
+
+/// Drop repeated `-I`/`-isystem` directories, keeping the first occurrence of
+/// each since include order matters. `-I dir` and `-Idir` are the same.
+pub(crate) fn dedup_include_args(args: Vec<String>) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    let mut result = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(flag) = ["-isystem", "-I"]
+            .into_iter()
+            .find(|flag| arg.starts_with(flag))
+        else {
+            result.push(arg);
+            continue;
+        };
+
+        let (dir, group) = if arg == flag {
+            match args.next() {
+                Some(dir) => (dir.clone(), vec![arg, dir]),
+                None => {
+                    result.push(arg);
+                    break;
+                }
+            }
+        } else {
+            (arg[flag.len()..].to_string(), vec![arg])
+        };
+        if seen.insert((flag, dir)) {
+            result.extend(group);
+        }
+    }
+    result
+}
+
+/// Rebase every entry onto `root`: `file` becomes relative to `root` and
+/// `directory` is set to `root`, so the two still resolve to the same source.
+/// Files that cannot be expressed relative to `root` (e.g. on another drive)
+/// are left as absolute paths. Other relative paths in the command are not
+/// rewritten.
+pub fn make_relative_to(commands: &mut [CompileCommand], root: &Path) {
+    let root = normalize_path(&std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf()));
+    for cmd in commands.iter_mut() {
+        let file = normalize_path(&Path::new(&cmd.directory).join(&cmd.file));
+        cmd.file = relative_path(&file, &root)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .into_owned();
+        cmd.directory = root.to_string_lossy().into_owned();
+    }
+}
+
+/// Set every entry's `directory` to `directory`, first joining `file` with
+/// the original directory so it stays valid. Other relative paths in the
+/// command are not rewritten.
+pub fn override_directory(commands: &mut [CompileCommand], directory: &Path) {
+    let directory = directory.to_string_lossy();
+    for cmd in commands.iter_mut() {
+        let file = normalize_path(&Path::new(&cmd.directory).join(&cmd.file));
+        cmd.file = file.to_string_lossy().into_owned();
+        cmd.directory = directory.to_string();
+    }
+}
+
+/// Lexically resolve `.` and `..` components without touching the filesystem
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !result.pop() {
+                    result.push(component);
+                }
+            }
+            _ => result.push(component),
+        }
+    }
+    result
+}
+
+/// Express `path` relative to `base`, or `None` if they share no common root
+pub(crate) fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    let path_components: Vec<_> = path.components().collect();
+    let base_components: Vec<_> = base.components().collect();
+
+    // Paths on different roots (or drives) cannot be related
+    match (path_components.first(), base_components.first()) {
+        (Some(a), Some(b)) if a == b => {}
+        _ => return None,
+    }
+
+    let common = path_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut result = PathBuf::new();
+    for _ in common..base_components.len() {
+        result.push("..");
+    }
+    for component in &path_components[common..] {
+        result.push(component);
+    }
+    Some(result)
+}
+
+/// Apply the output post-processing options from `config`
+/// (`normalize_separators`, `normalize_compiler`, `replace_compiler`,
+/// `output_extension_map`, `inject_flags`, `prepend_includes`, `env_vars`,
+/// `working_dir_override`, `relative_to`)
+pub fn apply_output_options(commands: &mut [CompileCommand], config: &Config) {
+    if config.normalize_separators {
+        for cmd in commands.iter_mut() {
+            cmd.normalize_path_separators();
+        }
+    }
+    if config.normalize_compiler {
+        normalize_compilers(commands);
+    }
+    if let Some(compiler) = config.replace_compiler.as_deref() {
+        replace_compiler_all(commands, compiler);
+    }
+    if !config.output_extension_map.is_empty() {
+        map_output_extensions(commands, &config.output_extension_map);
+    }
+    if !config.inject_flags.is_empty() {
+        let flags: Vec<&str> = config.inject_flags.iter().map(String::as_str).collect();
+        inject_flags(commands, &flags);
+    }
+    if !config.prepend_includes.is_empty() {
+        for cmd in commands.iter_mut() {
+            *cmd = cmd.with_prepended_includes(&config.prepend_includes);
+        }
+    }
+    prepend_env_vars(commands, &config.env_vars);
+    if let Some(directory) = config.working_dir_override.as_ref() {
+        override_directory(commands, directory);
+    }
+    if let Some(root) = config.relative_to.as_ref() {
+        make_relative_to(commands, root);
+    }
+}
+
+/// Post-process a freshly parsed database according to `config`: output
+/// options, then `merge` or `append` with the existing output file,
+/// `hash_dedup`, `dedup`, `prune`, `sort` and `validate`
+pub fn finalize_database(
+    mut commands: Vec<CompileCommand>,
+    config: &Config,
+) -> Result<Vec<CompileCommand>, CompileDbError> {
+    apply_output_options(&mut commands, config);
+    if config.merge && config.output_file.exists() {
+        let existing = load_database(&config.output_file)?;
+        commands = merge_databases(existing, commands);
+    }
+    if config.append && config.output_file.exists() {
+        let mut existing = load_database(&config.output_file)?;
+        existing.extend(commands);
+        commands = dedup_commands(existing, MergeStrategy::KeepLast);
+    }
+    if config.hash_dedup {
+        let removed = remove_duplicate_commands(&mut commands);
+        tracing::debug!("Removed {removed} duplicate entries");
+    }
+    if let Some(strategy) = config.dedup {
+        commands = dedup_commands(commands, strategy);
+    }
+    if config.prune && !config.no_strict {
+        let pruned = prune_missing(&mut commands);
+        tracing::info!("Pruned {pruned} entries with missing source files");
+    }
+    if config.sort {
+        sort_commands(&mut commands);
+    }
+    if config.validate {
+        validate_commands(&commands)?;
+    }
+    Ok(commands)
+}
+
+/// Run make's dry run in `config.build_dir` and return the finished database.
+/// The real build is not run.
+pub fn run_make_and_collect(
+    args: &[&str],
+    config: &Config,
+) -> Result<Vec<CompileCommand>, CompileDbError> {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let commands = make_wrapper::MakeWrapper::new().execute(&args, config)?;
+    finalize_database(commands, config)
+}
+
+/// Like [`run_make_and_collect`], then write the database to
+/// `config.output_file`, returning the number of entries written
+pub fn run_make_and_write(args: &[&str], config: &Config) -> Result<usize, CompileDbError> {
+    let commands = run_make_and_collect(args, config)?;
+    let file = std::fs::File::create(&config.output_file)?;
+    write_commands(std::io::BufWriter::new(file), &commands, config.format)?;
+    Ok(commands.len())
+}
+
+/// Serialize `commands` to `writer` in the given format
+pub fn write_commands<W: std::io::Write>(
+    mut writer: W,
+    commands: &[CompileCommand],
+    format: OutputFormat,
+) -> Result<(), CompileDbError> {
+    match format {
+        OutputFormat::Json => serde_json::to_writer_pretty(&mut writer, commands)?,
+        OutputFormat::Jsonl => {
+            for cmd in commands {
+                serde_json::to_writer(&mut writer, cmd)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Sort entries by `file`, then `directory`, keeping the original order of ties
+pub fn sort_commands(commands: &mut [CompileCommand]) {
+    commands.sort_by(|a, b| {
+        a.file
+            .cmp(&b.file)
+            .then_with(|| a.directory.cmp(&b.directory))
+    });
+}
+
+/// Merge `new` entries into `existing`. Entries for the same `directory`/`file`
+/// are replaced in place; new translation units are appended.
+pub fn merge_databases(
+    existing: Vec<CompileCommand>,
+    new: Vec<CompileCommand>,
+) -> Vec<CompileCommand> {
+    let mut merged = existing;
+    let mut index: std::collections::HashMap<PathBuf, usize> = merged
+        .iter()
+        .enumerate()
+        .map(|(i, cmd)| (Path::new(&cmd.directory).join(&cmd.file), i))
+        .collect();
+
+    for cmd in new {
+        let unit = Path::new(&cmd.directory).join(&cmd.file);
+        match index.get(&unit) {
+            Some(&i) => merged[i] = cmd,
+            None => {
+                index.insert(unit, merged.len());
+                merged.push(cmd);
+            }
+        }
+    }
+    merged
+}
+
+/// [`CompileCommand::validate`] every entry, failing with the index of the
+/// first invalid one
+pub fn validate_commands(commands: &[CompileCommand]) -> Result<(), CompileDbError> {
+    for (index, cmd) in commands.iter().enumerate() {
+        if let Some(reason) = cmd.validation_error() {
+            return Err(CompileDbError::InvalidEntry {
+                index,
+                reason: reason.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Keep one entry per `directory`/`file` according to `strategy`. Each unit
+/// stays at the position of its first appearance.
+pub fn dedup_commands(
+    commands: Vec<CompileCommand>,
+    strategy: MergeStrategy,
+) -> Vec<CompileCommand> {
+    let flag_count = |cmd: &CompileCommand| match (&cmd.arguments, &cmd.command) {
+        (Some(arguments), _) => arguments.len(),
+        (None, Some(command)) => command.len(),
+        (None, None) => 0,
+    };
+
+    let mut units: IndexMap<PathBuf, CompileCommand> = IndexMap::new();
+    for cmd in commands {
+        let unit = Path::new(&cmd.directory).join(&cmd.file);
+        match units.entry(unit) {
+            indexmap::map::Entry::Vacant(entry) => {
+                entry.insert(cmd);
+            }
+            indexmap::map::Entry::Occupied(mut entry) => {
+                let replace = match strategy {
+                    MergeStrategy::KeepFirst => false,
+                    MergeStrategy::KeepLast => true,
+                    MergeStrategy::KeepMostFlags => flag_count(&cmd) > flag_count(entry.get()),
+                };
+                if replace {
+                    entry.insert(cmd);
+                }
+            }
+        }
+    }
+    units.into_values().collect()
+}
+
+/// Drop entries that repeat an earlier entry's `file`, `directory` and
+/// command exactly, returning the number of entries removed. Unlike
+/// [`dedup_commands`], a unit compiled with different flags keeps every
+/// variant. Commands are compared by their hash.
+pub fn remove_duplicate_commands(commands: &mut Vec<CompileCommand>) -> usize {
+    let before = commands.len();
+    let mut seen: FxHashSet<(String, String, u64)> = FxHashSet::default();
+    commands.retain(|cmd| {
+        let hash = FxBuildHasher.hash_one((&cmd.command, &cmd.arguments, &cmd.output));
+        seen.insert((cmd.file.clone(), cmd.directory.clone(), hash))
+    });
+    before - commands.len()
+}
+
+/// Group entries by their `directory`, in order of first appearance
+pub fn group_by_directory(commands: &[CompileCommand]) -> IndexMap<PathBuf, Vec<&CompileCommand>> {
+    let mut groups: IndexMap<PathBuf, Vec<&CompileCommand>> = IndexMap::new();
+    for cmd in commands {
+        groups
+            .entry(PathBuf::from(&cmd.directory))
+            .or_default()
+            .push(cmd);
+    }
+    groups
+}
+
+/// Map each source file, resolved against its `directory`, to the last entry
+/// that compiles it
+pub fn group_by_file(commands: &[CompileCommand]) -> IndexMap<PathBuf, &CompileCommand> {
+    let mut files = IndexMap::new();
+    for cmd in commands {
+        files.insert(Path::new(&cmd.directory).join(&cmd.file), cmd);
+    }
+    files
+}
+
+/// Map each include directory (see [`CompileCommand::include_dirs`]) to the
+/// translation units compiled with it, in order of first appearance
+pub fn header_map(commands: &[CompileCommand]) -> IndexMap<PathBuf, Vec<PathBuf>> {
+    let mut map: IndexMap<PathBuf, Vec<PathBuf>> = IndexMap::new();
+    for cmd in commands {
+        let unit = normalize_path(&Path::new(&cmd.directory).join(&cmd.file));
+        for dir in cmd.include_dirs() {
+            let units = map.entry(dir).or_default();
+            if !units.contains(&unit) {
+                units.push(unit.clone());
+            }
+        }
+    }
+    map
+}
+
+/// Drop entries whose `directory`/`file` no longer exists on disk, returning
+/// the number of entries removed
+pub fn prune_missing(commands: &mut Vec<CompileCommand>) -> usize {
+    let before = commands.len();
+    commands.retain(|cmd| Path::new(&cmd.directory).join(&cmd.file).exists());
+    before - commands.len()
+}
+
+/// Load an existing compilation database from disk, in either [`OutputFormat`]
+pub fn load_database(path: &Path) -> Result<Vec<CompileCommand>, CompileDbError> {
+    let contents = std::fs::read_to_string(path)?;
+    parse_database(&contents)
+}
+
+/// Parse a compilation database written in either [`OutputFormat`]: a JSON
+/// array, or one JSON object per line
+pub fn parse_database(contents: &str) -> Result<Vec<CompileCommand>, CompileDbError> {
+    if contents.trim_start().starts_with('[') {
+        return Ok(serde_json::from_str(contents)?);
+    }
+    serde_json::Deserializer::from_str(contents)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .map_err(CompileDbError::Json)
+}
+
+/// Translation units that differ between two databases, keyed by `directory`/`file`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatabaseDiff {
+    /// Units only present in the new database
+    pub added: Vec<String>,
+
+    /// Units only present in the old database
+    pub removed: Vec<String>,
+
+    /// Units present in both whose entries differ
+    pub updated: Vec<String>,
+}
+
+impl DatabaseDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+    }
+}
+
+/// Compare two databases by translation unit
+pub fn diff_databases(old: &[CompileCommand], new: &[CompileCommand]) -> DatabaseDiff {
+    let unit = |cmd: &CompileCommand| {
+        Path::new(&cmd.directory)
+            .join(&cmd.file)
+            .to_string_lossy()
+            .into_owned()
+    };
+    let old_units: std::collections::BTreeMap<_, _> =
+        old.iter().map(|cmd| (unit(cmd), cmd)).collect();
+    let new_units: std::collections::BTreeMap<_, _> =
+        new.iter().map(|cmd| (unit(cmd), cmd)).collect();
+
+    let mut diff = DatabaseDiff::default();
+    for (name, cmd) in &new_units {
+        match old_units.get(name) {
+            None => diff.added.push(name.clone()),
+            Some(old_cmd) if old_cmd != cmd => diff.updated.push(name.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old_units
+        .keys()
+        .filter(|name| !new_units.contains_key(*name))
+        .cloned()
+        .collect();
+    diff
+}
+
+/// Substitute `$VAR` and `${VAR}` references from the environment. Unknown
+/// variables are left as written.
+pub(crate) fn expand_env_vars(text: &str) -> String {
+    static VAR_REGEX: std::sync::LazyLock<regex::Regex> = std::sync::LazyLock::new(|| {
+        regex::Regex::new(r"\$(?:\{([A-Za-z_][A-Za-z0-9_]*)\}|([A-Za-z_][A-Za-z0-9_]*))").unwrap()
+    });
+
+    VAR_REGEX
+        .replace_all(text, |caps: &regex::Captures| {
+            let name = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+            std::env::var(name).unwrap_or_else(|_| {
+                tracing::warn!("Environment variable {name} is not set, leaving it unexpanded");
+                caps[0].to_string()
+            })
+        })
+        .into_owned()
+}
+
+/// Expand a leading `~` to the user's home directory, leaving other paths untouched
+pub(crate) fn expand_tilde(path: &Path) -> PathBuf {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) if rest.as_os_str().is_empty() => PathBuf::from(home),
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
 
-    /// The command as a list of arguments (when command_style is false)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub arguments: Option<Vec<String>>,
+/// Environment variable that sets the initial build directory
+pub const BUILD_DIR_ENV: &str = "COMPILEDB_BUILD_DIR";
 
-    /// Optional output file
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub output: Option<String>,
+/// Build directory from `COMPILEDB_BUILD_DIR`, if set and non-empty
+pub fn build_dir_from_env() -> Option<PathBuf> {
+    std::env::var_os(BUILD_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
 }
 
+/// Default `regex_compile`: a known C, C++ or Fortran compiler name, with an
+/// optional directory and version suffix (`clang-14`, `g++-12`)
+pub const DEFAULT_REGEX_COMPILE: &str = r"(?:[^/]*/)*(gcc|clang|clang-cl|clang-cpp|clang-cc1|cc|g\+\+|c\+\+|clang\+\+|cl|gfortran|ifort|ifx|pgfortran|nvfortran|flang|flang-new)(?:-[0-9\.]+)?(?:\s|$)";
+
+/// Default `regex_file`: the source after `-c` and before `-o`, a Fortran
+/// source after `-fc`, a positional source before `-o` (compile and link in
+/// one step), or the first C/C++ source after MSVC-style `/c` and its `/`
+/// options
+pub const DEFAULT_REGEX_FILE: &str = r"\s-c\s+(\S+\.(c|cpp|cc|cxx|c\+\+|s|m|mm|cu|f|f90|f95|f03|f08|for|fpp))\s+-o\s|\s-fc\s+(\S+\.(?:f|f90|f95|f03|f08|for|fpp))(?:\s|$)|\s(\S+\.(?:c|cpp|cc|cxx|c\+\+|m|mm|cu|f|f90|f95|f03|f08|for|fpp))\s+-o\s|\s/c\s+(?:/\S+\s+)*(\S+\.(?:c|cpp|cc|cxx|c\+\+))(?:\s|$)";
+
 /// Configuration for the compilation database generator
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /// Path to the build log file
     pub build_log: Option<PathBuf>,
 
+    /// Build system that wrote the build log
+    pub log_format: LogFormat,
+
     /// Output file path
     pub output_file: PathBuf,
 
-    /// Initial build directory
+    /// Format of the output file
+    pub format: OutputFormat,
+
+    /// Initial build directory. Defaults to `COMPILEDB_BUILD_DIR` if set,
+    /// otherwise the current directory.
     pub build_dir: PathBuf,
 
     /// File exclusion patterns
     pub exclude_patterns: Vec<String>,
 
+    /// File inclusion patterns; when set, only files matching one are kept.
+    /// Exclusion patterns take precedence.
+    pub include_patterns: Vec<String>,
+
+    /// Directory exclusion patterns, matched against the resolved working directory
+    pub exclude_dir_patterns: Vec<String>,
+
+    /// Flags passed to make for the dry run that lists compile commands
+    pub make_flags: Vec<String>,
+
+    /// Environment variables set for the make subprocess (dry run and build)
+    pub make_env: Vec<(String, String)>,
+
+    /// Start the make subprocess from an empty environment, plus `make_env`
+    pub clear_make_env: bool,
+
     /// Skip actual build
     pub no_build: bool,
 
+    /// Report what would be written instead of writing the output file
+    pub dry_run: bool,
+
     /// Enable verbose output
     pub verbose: u8,
 
     /// Skip source file existence check
     pub no_strict: bool,
 
+    /// Keep commands whose source file is missing, logging a warning instead
+    pub warn_missing: bool,
+
+    /// In strict mode, also require `-include`/`-imacros` files to exist
+    pub strict_includes: bool,
+
     /// Predefined compiler macros
     pub macros: Vec<String>,
 
     /// Use command style output
     pub command_style: bool,
 
+    /// Also write a database in the other style next to the output file
+    pub emit_both: bool,
+
     /// Use full compiler path
     pub full_path: bool,
 
+    /// Only keep commands whose compiler basename is in this list (when non-empty)
+    pub compiler_filter: Vec<String>,
+
     /// Regex pattern for compile commands
     pub regex_compile: String,
 
+    /// Regex for a launcher prefix (e.g. `buildwrap --opt`) stripped from the
+    /// start of each command before the compiler is looked up
+    pub compiler_wrapper_regex: Option<String>,
+
     /// Regex pattern for source files
     pub regex_file: String,
+
+    /// Additional source file extensions recognized alongside `regex_file`
+    /// (`target_extensions` is accepted as an alias in config files)
+    #[serde(alias = "target_extensions")]
+    pub source_extensions: Vec<String>,
+
+    /// Strip dependency-generation flags from parsed commands
+    pub strip_deps: bool,
+
+    /// Also write a JSON map from include directories to translation units here
+    pub emit_header_map: Option<PathBuf>,
+
+    /// Reduce the compiler to its bare name, without directory, target
+    /// triple or version suffix
+    pub normalize_compiler: bool,
+
+    /// Compiler written in place of the one found in the build log
+    pub replace_compiler: Option<String>,
+
+    /// Output extensions to rewrite, as `(from, to)` pairs like `("o", "obj")`
+    pub output_extension_map: Vec<(String, String)>,
+
+    /// Extra flags appended to every command before writing
+    pub inject_flags: Vec<String>,
+
+    /// Include directories inserted before every command's existing include flags
+    pub prepend_includes: Vec<PathBuf>,
+
+    /// Rebase all entries so `file` is relative to this root and `directory` is the root
+    pub relative_to: Option<PathBuf>,
+
+    /// Write this `directory` for every entry, making each `file` absolute first
+    pub working_dir_override: Option<PathBuf>,
+
+    /// Write `/` instead of `\` in `directory`, `file` and Windows path arguments
+    pub normalize_separators: bool,
+
+    /// Fail if fewer than this many compile commands are found
+    pub min_commands: Option<usize>,
+
+    /// Stop parsing after this many consecutive lines fail to match the compile regex
+    pub max_consecutive_non_matching: Option<usize>,
+
+    /// Print parse statistics after parsing
+    pub print_stats: bool,
+
+    /// Sort the database by file and directory before writing
+    pub sort: bool,
+
+    /// Merge new entries into the existing output file instead of replacing it
+    pub merge: bool,
+
+    /// Add new entries to the existing output file, keeping the last entry
+    /// for each `directory`/`file`
+    pub append: bool,
+
+    /// Drop entries whose source file no longer exists (ignored with `no_strict`)
+    pub prune: bool,
+
+    /// Drop entries that exactly repeat an earlier one
+    pub hash_dedup: bool,
+
+    /// Keep a single entry per translation unit, chosen by this strategy
+    pub dedup: Option<MergeStrategy>,
+
+    /// Check every entry against the spec before writing
+    pub validate: bool,
+
+    /// Lines of the build's stderr to include when it fails
+    pub error_context_lines: usize,
+
+    /// Replace invalid UTF-8 in the build output instead of failing on it
+    pub keep_going: bool,
+
+    /// Substitute `$VAR` and `${VAR}` in parsed file paths and directories
+    pub expand_env_vars: bool,
+
+    /// Substitute `$(CURDIR)` and `${CURDIR}` left unexpanded in printed recipes
+    pub expand_make_vars: bool,
+
+    /// `(from, to)` prefix rewrites applied to include, source and output paths
+    pub rewrite_paths: Vec<(String, String)>,
+
+    /// Environment variables prepended to command-style entries
+    pub env_vars: Vec<(String, String)>,
+
+    /// C compiler also recognized as a compiler, defaults to `$CC`
+    pub cc: Option<String>,
+
+    /// C++ compiler also recognized as a compiler, defaults to `$CXX`
+    pub cxx: Option<String>,
+
+    /// Warn when a long log has no `Entering directory` lines, which means
+    /// make's `-w` was off and directories cannot be tracked
+    pub warn_no_enter_dir: bool,
+
+    /// Print the number of lines and commands processed to stderr periodically,
+    /// or a spinner while make runs. From a config file, only when stderr is a
+    /// terminal.
+    pub progress: bool,
+
+    /// Drop matched commands run by a linker (`ld`, `lld`, `gold`, `link.exe`)
+    /// rather than a compiler driver
+    pub filter_linker_commands: bool,
+
+    /// Drop the `Note: including file:` lines printed by MSVC's `/showIncludes`
+    pub filter_msvc_includes: bool,
+
+    /// Only keep translation units in these languages (when non-empty)
+    pub languages: Vec<Language>,
+
+    /// Remove repeated `-I`/`-isystem` directories within each command
+    pub dedup_includes: bool,
+
+    /// Replace `@file` arguments with the contents of the response file
+    pub expand_response_files: bool,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             build_log: None,
+            log_format: LogFormat::Make,
             output_file: PathBuf::from("compile_commands.json"),
-            build_dir: std::env::current_dir().unwrap_or_default(),
+            format: OutputFormat::Json,
+            build_dir: build_dir_from_env()
+                .unwrap_or_else(|| std::env::current_dir().unwrap_or_default()),
             exclude_patterns: Vec::new(),
+            include_patterns: Vec::new(),
+            exclude_dir_patterns: Vec::new(),
+            make_flags: ["-B", "-n", "-k", "-w"].map(String::from).to_vec(),
+            make_env: Vec::new(),
+            clear_make_env: false,
             no_build: false,
+            dry_run: false,
             verbose: 0,
             no_strict: false,
+            warn_missing: false,
+            strict_includes: false,
             macros: Vec::new(),
             command_style: false,
+            emit_both: false,
             full_path: false,
-            regex_compile: String::from(
-                r"(?:[^/]*/)*(gcc|clang|cc|g\+\+|c\+\+|clang\+\+|cl)(?:-[0-9\.]+)?(?:\s|$)",
-            ),
-            regex_file: String::from(r"\s-c\s+(\S+\.(c|cpp|cc|cxx|c\+\+|s|m|mm|cu))\s+-o\s"),
+            compiler_filter: Vec::new(),
+            regex_compile: String::from(DEFAULT_REGEX_COMPILE),
+            compiler_wrapper_regex: None,
+            regex_file: String::from(DEFAULT_REGEX_FILE),
+            source_extensions: Vec::new(),
+            strip_deps: false,
+            inject_flags: Vec::new(),
+            emit_header_map: None,
+            normalize_compiler: false,
+            replace_compiler: None,
+            output_extension_map: Vec::new(),
+            prepend_includes: Vec::new(),
+            relative_to: None,
+            working_dir_override: None,
+            normalize_separators: false,
+            min_commands: None,
+            max_consecutive_non_matching: None,
+            print_stats: false,
+            sort: false,
+            merge: false,
+            append: false,
+            prune: false,
+            hash_dedup: false,
+            dedup: None,
+            validate: false,
+            error_context_lines: 20,
+            keep_going: false,
+            expand_env_vars: false,
+            expand_make_vars: false,
+            rewrite_paths: Vec::new(),
+            env_vars: Vec::new(),
+            cc: std::env::var("CC").ok().filter(|cc| !cc.is_empty()),
+            cxx: std::env::var("CXX").ok().filter(|cxx| !cxx.is_empty()),
+            warn_no_enter_dir: true,
+            progress: false,
+            filter_linker_commands: true,
+            filter_msvc_includes: true,
+            languages: Vec::new(),
+            dedup_includes: false,
+            expand_response_files: false,
         }
     }
 }
 
+/// Builder for [`Config`], starting from [`Config::default`].
+///
+/// Each setter is named after the field it sets. Setters for optional
+/// fields take the inner value.
+///
+/// ```
+/// use compiledb::Config;
+///
+/// let config = Config::builder()
+///     .build_log("build.log")
+///     .command_style(true)
+///     .build();
+/// assert_eq!(config.build_log.as_deref(), Some("build.log".as_ref()));
+/// assert!(config.command_style);
+/// assert_eq!(config.output_file, Config::default().output_file);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+macro_rules! config_setters {
+    (
+        values { $($field:ident: $ty:ty,)* }
+        options { $($opt_field:ident: $opt_ty:ty,)* }
+    ) => {
+        $(
+            #[doc = concat!("Set [`Config::", stringify!($field), "`]")]
+            pub fn $field(mut self, value: impl Into<$ty>) -> Self {
+                self.config.$field = value.into();
+                self
+            }
+        )*
+        $(
+            #[doc = concat!("Set [`Config::", stringify!($opt_field), "`]")]
+            pub fn $opt_field(mut self, value: impl Into<$opt_ty>) -> Self {
+                self.config.$opt_field = Some(value.into());
+                self
+            }
+        )*
+    };
+}
+
+impl ConfigBuilder {
+    config_setters! {
+        values {
+            log_format: LogFormat,
+            output_file: PathBuf,
+            format: OutputFormat,
+            build_dir: PathBuf,
+            exclude_patterns: Vec<String>,
+            include_patterns: Vec<String>,
+            exclude_dir_patterns: Vec<String>,
+            make_flags: Vec<String>,
+            make_env: Vec<(String, String)>,
+            clear_make_env: bool,
+            no_build: bool,
+            dry_run: bool,
+            verbose: u8,
+            no_strict: bool,
+            warn_missing: bool,
+            strict_includes: bool,
+            macros: Vec<String>,
+            command_style: bool,
+            emit_both: bool,
+            full_path: bool,
+            normalize_compiler: bool,
+            compiler_filter: Vec<String>,
+            regex_compile: String,
+            regex_file: String,
+            source_extensions: Vec<String>,
+            strip_deps: bool,
+            output_extension_map: Vec<(String, String)>,
+            inject_flags: Vec<String>,
+            prepend_includes: Vec<PathBuf>,
+            normalize_separators: bool,
+            print_stats: bool,
+            sort: bool,
+            merge: bool,
+            append: bool,
+            prune: bool,
+            hash_dedup: bool,
+            validate: bool,
+            error_context_lines: usize,
+            keep_going: bool,
+            expand_env_vars: bool,
+            expand_make_vars: bool,
+            rewrite_paths: Vec<(String, String)>,
+            env_vars: Vec<(String, String)>,
+            warn_no_enter_dir: bool,
+            progress: bool,
+            filter_linker_commands: bool,
+            filter_msvc_includes: bool,
+            languages: Vec<Language>,
+            dedup_includes: bool,
+            expand_response_files: bool,
+        }
+        options {
+            build_log: PathBuf,
+            compiler_wrapper_regex: String,
+            emit_header_map: PathBuf,
+            replace_compiler: String,
+            relative_to: PathBuf,
+            working_dir_override: PathBuf,
+            min_commands: usize,
+            max_consecutive_non_matching: usize,
+            dedup: MergeStrategy,
+            cc: String,
+            cxx: String,
+        }
+    }
+
+    /// Finish building the configuration
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+impl Config {
+    /// Start a [`ConfigBuilder`] from the default configuration
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Load a configuration from a TOML file. Keys mirror the field names and
+    /// any field left out keeps its default.
+    pub fn from_toml_file(path: &Path) -> Result<Config, CompileDbError> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| CompileDbError::TomlError(e.to_string()))
+    }
+
+    /// Load a configuration written by [`save`](Self::save): JSON if the file
+    /// ends in `.json`, TOML otherwise
+    pub fn from_file(path: &Path) -> Result<Config, CompileDbError> {
+        if is_json_path(path) {
+            let contents = std::fs::read_to_string(path)?;
+            Ok(serde_json::from_str(&contents)?)
+        } else {
+            Config::from_toml_file(path)
+        }
+    }
+
+    /// Write every setting to `path`, as JSON if it ends in `.json` and as
+    /// TOML otherwise
+    pub fn save(&self, path: &Path) -> Result<(), CompileDbError> {
+        let contents = if is_json_path(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            toml::to_string(self).map_err(|e| CompileDbError::TomlError(e.to_string()))?
+        };
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+fn is_json_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
 /// Main interface for generating compilation database
 pub trait CompileDbGenerator {
     /// Generate compilation database from build log
@@ -113,10 +1698,64 @@ pub trait CompileDbGenerator {
     -> Result<(), CompileDbError>;
 }
 
+/// In-place transforms over a whole database
+pub trait CompileCommandsExt {
+    /// Replace every entry with `f(entry)`, dropping those that map to `None`
+    fn filter_map_commands<F>(&mut self, f: F)
+    where
+        F: FnMut(CompileCommand) -> Option<CompileCommand>;
+
+    /// Replace every entry with `f(entry)`
+    fn map_commands<F>(&mut self, f: F)
+    where
+        F: FnMut(CompileCommand) -> CompileCommand;
+}
+
+impl CompileCommandsExt for Vec<CompileCommand> {
+    fn filter_map_commands<F>(&mut self, f: F)
+    where
+        F: FnMut(CompileCommand) -> Option<CompileCommand>,
+    {
+        *self = std::mem::take(self).into_iter().filter_map(f).collect();
+    }
+
+    fn map_commands<F>(&mut self, f: F)
+    where
+        F: FnMut(CompileCommand) -> CompileCommand,
+    {
+        *self = std::mem::take(self).into_iter().map(f).collect();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A valid entry for `file` in `directory`, compiled with `command`
+    fn command_entry(directory: &str, file: &str, command: &str) -> CompileCommand {
+        CompileCommand::builder()
+            .directory(directory)
+            .file(file)
+            .command(command)
+            .build()
+            .unwrap()
+    }
+
+    /// A valid entry for `file` in `directory`, compiled with `arguments`
+    fn arguments_entry(directory: &str, file: &str, arguments: &[&str]) -> CompileCommand {
+        CompileCommand::builder()
+            .directory(directory)
+            .file(file)
+            .arguments(arguments.iter().copied())
+            .build()
+            .unwrap()
+    }
+
+    /// A plain `cc -c` entry, for tests that only look at `directory` and `file`
+    fn entry(directory: &str, file: &str) -> CompileCommand {
+        command_entry(directory, file, &format!("cc -c {file}"))
+    }
+
     #[test]
     fn test_config_default() {
         let config = Config::default();
@@ -127,6 +1766,195 @@ mod tests {
         assert!(!config.full_path);
     }
 
+    #[test]
+    fn test_config_from_toml_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("compiledb.toml");
+        std::fs::write(
+            &path,
+            "no_strict = true\nexclude_patterns = [\"third_party/\"]\nmin_commands = 10\n",
+        )
+        .unwrap();
+
+        let config = Config::from_toml_file(&path).unwrap();
+        assert!(config.no_strict);
+        assert_eq!(config.exclude_patterns, vec!["third_party/"]);
+        assert_eq!(config.min_commands, Some(10));
+        // Fields missing from the file keep their defaults
+        assert_eq!(config.make_flags, Config::default().make_flags);
+
+        std::fs::write(&path, "target_extensions = [\"f90\", \"S\"]\n").unwrap();
+        let config = Config::from_toml_file(&path).unwrap();
+        assert_eq!(config.source_extensions, vec!["f90", "S"]);
+
+        std::fs::write(&path, "no_strikt = true\n").unwrap();
+        assert!(matches!(
+            Config::from_toml_file(&path),
+            Err(CompileDbError::TomlError(_))
+        ));
+    }
+
+    #[test]
+    fn test_config_save_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            build_dir: PathBuf::from("/src/project"),
+            emit_header_map: Some(PathBuf::from("headers.json")),
+            rewrite_paths: vec![(String::from("/build"), String::from("/src"))],
+            languages: vec![Language::Cpp],
+            dedup: Some(MergeStrategy::KeepMostFlags),
+            format: OutputFormat::Jsonl,
+            ..Config::default()
+        };
+
+        for name in ["compiledb.toml", "compiledb.json"] {
+            let path = dir.path().join(name);
+            config.save(&path).unwrap();
+            let loaded = Config::from_file(&path).unwrap();
+            assert_eq!(format!("{loaded:?}"), format!("{config:?}"));
+        }
+    }
+
+    #[test]
+    fn test_group_by_directory_and_file() {
+        let entry = |directory: &str, file: &str, compiler: &str| {
+            command_entry(directory, file, &format!("{compiler} -c {file}"))
+        };
+        let commands = vec![
+            entry("/src/lib", "a.c", "cc"),
+            entry("/src/app", "main.c", "cc"),
+            entry("/src/lib", "b.c", "cc"),
+            entry("/src/lib", "a.c", "clang"),
+            entry("/src/app", "/src/lib/b.c", "gcc"),
+        ];
+
+        let by_dir = group_by_directory(&commands);
+        let dirs: Vec<_> = by_dir.keys().map(|dir| dir.to_str().unwrap()).collect();
+        assert_eq!(dirs, vec!["/src/lib", "/src/app"]);
+        let lib_files: Vec<_> = by_dir[Path::new("/src/lib")]
+            .iter()
+            .map(|cmd| cmd.file.as_str())
+            .collect();
+        assert_eq!(lib_files, vec!["a.c", "b.c", "a.c"]);
+        assert_eq!(by_dir[Path::new("/src/app")].len(), 2);
+
+        // Later entries win, absolute files resolve to the same unit
+        let by_file = group_by_file(&commands);
+        let files: Vec<_> = by_file
+            .iter()
+            .map(|(file, cmd)| (file.to_str().unwrap(), cmd.compiler().unwrap()))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                ("/src/lib/a.c", "clang"),
+                ("/src/app/main.c", "cc"),
+                ("/src/lib/b.c", "gcc"),
+            ]
+        );
+        assert!(group_by_directory(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_header_map_groups_units_by_include_dir() {
+        let commands = vec![
+            command_entry("/src/lib", "a.c", "cc -I../include -isystem /usr/x -c a.c"),
+            command_entry("/src/app", "main.c", "cc -I /src/include -c main.c"),
+            command_entry("/src/app", "other.c", "cc -iquote. -c other.c"),
+        ];
+
+        let map = header_map(&commands);
+        let shared: Vec<_> = map[Path::new("/src/include")]
+            .iter()
+            .map(|unit| unit.to_str().unwrap())
+            .collect();
+        assert_eq!(shared, vec!["/src/lib/a.c", "/src/app/main.c"]);
+        assert_eq!(map[Path::new("/usr/x")].len(), 1);
+        assert_eq!(
+            map[Path::new("/src/app")],
+            [PathBuf::from("/src/app/other.c")]
+        );
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn test_language_detection() {
+        let entry = |file: &str, arguments: &[&str]| arguments_entry("/src", file, arguments);
+
+        assert_eq!(
+            entry("a.c", &["gcc", "-c", "a.c"]).language(),
+            Some(Language::C)
+        );
+        assert_eq!(
+            entry("a.cpp", &["g++", "-c", "a.cpp"]).language(),
+            Some(Language::Cpp)
+        );
+        assert_eq!(
+            entry("a.S", &["gcc", "-c", "a.S"]).language(),
+            Some(Language::Asm)
+        );
+        // -x overrides the extension, in both spellings
+        assert_eq!(
+            entry("a.c", &["clang", "-x", "c++", "-c", "a.c"]).language(),
+            Some(Language::Cpp)
+        );
+        assert_eq!(
+            entry("a.inc", &["clang", "-xc", "-c", "a.inc"]).language(),
+            Some(Language::C)
+        );
+        assert_eq!(entry("a.inc", &["gcc", "-c", "a.inc"]).language(), None);
+
+        let command_style = entry("a.c", &["gcc", "-x", "cuda", "-c", "a.c"]).to_command_style();
+        assert_eq!(command_style.language(), Some(Language::Cuda));
+        assert_eq!("cpp".parse(), Ok(Language::Cpp));
+        assert!("rust".parse::<Language>().is_err());
+    }
+
+    #[test]
+    fn test_compiler_accessors() {
+        let mut cmd = CompileCommand {
+            directory: String::from("/src"),
+            file: String::from("a.c"),
+            command: None,
+            arguments: Some(["cc", "-O2", "-c", "a.c"].map(String::from).to_vec()),
+            output: None,
+        };
+        assert_eq!(cmd.compiler(), Some("cc"));
+        assert_eq!(cmd.source_file_arg_index(), Some(3));
+
+        cmd = cmd.to_command_style();
+        assert_eq!(cmd.compiler(), Some("cc"));
+        assert_eq!(cmd.source_file_arg_index(), None);
+
+        cmd.command = None;
+        assert_eq!(cmd.compiler(), None);
+    }
+
+    #[test]
+    fn test_command_style_round_trip() {
+        let cmd = CompileCommand {
+            directory: String::from("/src"),
+            file: String::from("a.c"),
+            command: None,
+            arguments: Some(
+                ["gcc", "-DMSG=hello world", "-c", "a.c"]
+                    .map(String::from)
+                    .to_vec(),
+            ),
+            output: None,
+        };
+
+        let command_style = cmd.to_command_style();
+        assert_eq!(
+            command_style.command.as_deref(),
+            Some("gcc '-DMSG=hello world' -c a.c")
+        );
+        assert_eq!(command_style.arguments, None);
+
+        assert_eq!(command_style.to_arguments_style(), cmd);
+        assert_eq!(cmd.to_arguments_style(), cmd);
+    }
+
     #[test]
     fn test_compile_command_serialization() {
         let cmd = CompileCommand {
@@ -146,4 +1974,598 @@ mod tests {
         assert_eq!(cmd.arguments, decoded.arguments);
         assert_eq!(cmd.output, decoded.output);
     }
+
+    #[test]
+    fn test_strip_dependency_flags() {
+        let args = "gcc -MMD -MP -MT out/a.o -MF out/a.d -MFout/b.d -MD -MG -MQ q -c a.c -o a.o";
+        let mut commands = vec![
+            CompileCommand {
+                directory: String::from("/tmp"),
+                file: String::from("a.c"),
+                command: None,
+                arguments: Some(args.split_whitespace().map(String::from).collect()),
+                output: None,
+            },
+            CompileCommand {
+                directory: String::from("/tmp"),
+                file: String::from("a.c"),
+                command: Some(String::from(args)),
+                arguments: None,
+                output: None,
+            },
+        ];
+
+        strip_dependency_flags_all(&mut commands);
+
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap(),
+            &vec!["gcc", "-c", "a.c", "-o", "a.o"]
+        );
+        assert_eq!(commands[1].command.as_deref(), Some("gcc -c a.c -o a.o"));
+
+        // Quoted arguments in a command stay single words
+        let mut cmd = command_entry(
+            "/tmp",
+            "a.c",
+            r#"gcc -MD -MF "dep file.d" -DMSG="a  b" -c a.c"#,
+        );
+        cmd.strip_dependency_flags();
+        assert_eq!(cmd.command.as_deref(), Some("gcc '-DMSG=a  b' -c a.c"));
+    }
+
+    #[test]
+    fn test_diff_databases() {
+        let entry =
+            |file: &str, flag: &str| command_entry("/src", file, &format!("gcc {flag} -c {file}"));
+        let old = vec![
+            entry("a.c", "-O0"),
+            entry("b.c", "-O0"),
+            entry("c.c", "-O0"),
+        ];
+        let new = vec![
+            entry("a.c", "-O0"),
+            entry("b.c", "-O2"),
+            entry("d.c", "-O0"),
+        ];
+
+        let diff = diff_databases(&old, &new);
+        assert_eq!(diff.added, vec!["/src/d.c"]);
+        assert_eq!(diff.removed, vec!["/src/c.c"]);
+        assert_eq!(diff.updated, vec!["/src/b.c"]);
+        assert!(diff_databases(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_make_relative_to() {
+        let mut commands = vec![
+            entry("/project/out", "../src/a.c"),
+            entry("/project/out", "gen/b.c"),
+            entry("/other", "/elsewhere/c.c"),
+        ];
+
+        make_relative_to(&mut commands, Path::new("/project"));
+
+        assert_eq!(commands[0].file, "src/a.c");
+        assert_eq!(commands[1].file, "out/gen/b.c");
+        assert_eq!(commands[2].file, "../elsewhere/c.c");
+        assert!(commands.iter().all(|cmd| cmd.directory == "/project"));
+    }
+
+    #[test]
+    fn test_override_directory() {
+        let mut commands = vec![
+            entry("/project/out", "../src/a.c"),
+            entry("/project/lib", "b.c"),
+            entry("/other", "/elsewhere/c.c"),
+        ];
+
+        override_directory(&mut commands, Path::new("/project"));
+
+        let entries: Vec<_> = commands
+            .iter()
+            .map(|cmd| (cmd.directory.as_str(), cmd.file.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("/project", "/project/src/a.c"),
+                ("/project", "/project/lib/b.c"),
+                ("/project", "/elsewhere/c.c")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_commands() {
+        let mut commands = vec![entry("/b", "z.c"), entry("/b", "a.c"), entry("/a", "a.c")];
+
+        sort_commands(&mut commands);
+
+        let order: Vec<_> = commands
+            .iter()
+            .map(|cmd| (cmd.directory.as_str(), cmd.file.as_str()))
+            .collect();
+        assert_eq!(order, vec![("/a", "a.c"), ("/b", "a.c"), ("/b", "z.c")]);
+    }
+
+    #[test]
+    fn test_merge_databases() {
+        let entry =
+            |file: &str, flag: &str| command_entry("/src", file, &format!("gcc {flag} -c {file}"));
+        let existing = vec![entry("a.c", "-O0"), entry("b.c", "-O0")];
+        let new = vec![entry("b.c", "-O2"), entry("c.c", "-O2")];
+
+        let merged = merge_databases(existing, new);
+        assert_eq!(
+            merged,
+            vec![
+                entry("a.c", "-O0"),
+                entry("b.c", "-O2"),
+                entry("c.c", "-O2")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_command_builder() {
+        let cmd = CompileCommand::builder()
+            .directory("/src")
+            .file("a.c")
+            .arguments(["gcc", "-c", "a.c"])
+            .output("a.o")
+            .build()
+            .unwrap();
+        assert_eq!(
+            cmd,
+            CompileCommand {
+                directory: String::from("/src"),
+                file: String::from("a.c"),
+                command: None,
+                arguments: Some(["gcc", "-c", "a.c"].map(String::from).to_vec()),
+                output: Some(String::from("a.o")),
+            }
+        );
+
+        let builder = CompileCommand::builder().directory("/src").file("a.c");
+        assert!(builder.clone().command("gcc -c a.c").build().is_ok());
+        for invalid in [
+            builder.clone(),
+            builder.clone().command("gcc -c a.c").arguments(["gcc"]),
+            builder.clone().directory("").command("gcc -c a.c"),
+        ] {
+            assert!(matches!(
+                invalid.build(),
+                Err(CompileDbError::InvalidCommand(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = CompileCommand {
+            directory: String::from("/src"),
+            file: String::from("a.c"),
+            command: Some(String::from("gcc -c a.c")),
+            arguments: None,
+            output: None,
+        };
+        assert!(valid.validate().is_ok());
+
+        let invalid = [
+            (
+                CompileCommand {
+                    directory: String::new(),
+                    ..valid.clone()
+                },
+                "empty directory",
+            ),
+            (
+                CompileCommand {
+                    file: String::new(),
+                    ..valid.clone()
+                },
+                "empty file",
+            ),
+            (
+                CompileCommand {
+                    arguments: Some(vec![String::from("gcc")]),
+                    ..valid.clone()
+                },
+                "both command and arguments",
+            ),
+            (
+                CompileCommand {
+                    command: None,
+                    ..valid.clone()
+                },
+                "neither command nor arguments",
+            ),
+        ];
+        for (cmd, expected) in invalid {
+            match cmd.validate() {
+                Err(CompileDbError::InvalidCommand(reason)) => {
+                    assert!(reason.starts_with(expected))
+                }
+                other => panic!("expected InvalidCommand, got {other:?}"),
+            }
+            match validate_commands(&[valid.clone(), cmd]) {
+                Err(CompileDbError::InvalidEntry { index: 1, reason }) => {
+                    assert!(reason.starts_with(expected))
+                }
+                other => panic!("expected InvalidEntry at 1, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_remove_duplicate_commands() {
+        let entry = |file: &str, command: &str| command_entry("/src", file, command);
+        let mut commands = vec![
+            entry("a.c", "gcc -c a.c"),
+            entry("b.c", "gcc -c b.c"),
+            entry("a.c", "gcc -O2 -c a.c"),
+            entry("a.c", "gcc -c a.c"),
+            entry("b.c", "gcc -c b.c"),
+        ];
+
+        assert_eq!(remove_duplicate_commands(&mut commands), 2);
+        assert_eq!(
+            commands,
+            vec![
+                entry("a.c", "gcc -c a.c"),
+                entry("b.c", "gcc -c b.c"),
+                entry("a.c", "gcc -O2 -c a.c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_commands_ext() {
+        let entry = |file: &str| command_entry("/src", file, &format!("gcc -c {file}"));
+        let mut commands = vec![entry("a.c"), entry("gen/b.c"), entry("c.c")];
+
+        commands.filter_map_commands(|cmd| (!cmd.file.starts_with("gen/")).then_some(cmd));
+        commands.map_commands(|cmd| cmd.to_arguments_style());
+
+        let files: Vec<_> = commands.iter().map(|cmd| cmd.file.as_str()).collect();
+        assert_eq!(files, ["a.c", "c.c"]);
+        assert_eq!(
+            commands[1].arguments.as_ref().unwrap(),
+            &["gcc", "-c", "c.c"]
+        );
+    }
+
+    #[test]
+    fn test_dedup_commands() {
+        let entry = |file: &str, flags: &[&str]| {
+            arguments_entry("/src", file, &[&["gcc"], flags, &["-c", file]].concat())
+        };
+        let commands = vec![
+            entry("a.c", &["-O0"]),
+            entry("b.c", &[]),
+            entry("a.c", &["-O2", "-DNDEBUG", "-Iinc"]),
+            entry("a.c", &["-O2"]),
+        ];
+
+        let kept = |strategy| {
+            dedup_commands(commands.clone(), strategy)
+                .into_iter()
+                .map(|cmd| cmd.arguments.unwrap()[1].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kept(MergeStrategy::KeepFirst), ["-O0", "-c"]);
+        assert_eq!(kept(MergeStrategy::KeepLast), ["-O2", "-c"]);
+        assert_eq!(
+            dedup_commands(commands.clone(), MergeStrategy::KeepMostFlags),
+            vec![
+                entry("a.c", &["-O2", "-DNDEBUG", "-Iinc"]),
+                entry("b.c", &[])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_make_and_write() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("Makefile"),
+            "all: b.o a.o\n\
+             a.o:\n\tgcc -c a.c -o a.o\n\
+             b.o:\n\tgcc -c b.c -o b.o\n",
+        )
+        .unwrap();
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            output_file: dir.path().join("compile_commands.json"),
+            no_strict: true,
+            sort: true,
+            ..Config::default()
+        };
+
+        let collected = run_make_and_collect(&["all"], &config).unwrap();
+        let files: Vec<_> = collected.iter().map(|cmd| cmd.file.as_str()).collect();
+        assert_eq!(files, vec!["a.c", "b.c"]);
+
+        assert_eq!(run_make_and_write(&["all"], &config).unwrap(), 2);
+        assert_eq!(load_database(&config.output_file).unwrap(), collected);
+    }
+
+    #[test]
+    fn test_dedup_include_args() {
+        let args = [
+            "gcc",
+            "-Ia",
+            "-I",
+            "b",
+            "-I",
+            "a",
+            "-isystem",
+            "b",
+            "-Ib",
+            "-isystemb",
+            "-c",
+            "x.c",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            dedup_include_args(args),
+            ["gcc", "-Ia", "-I", "b", "-isystem", "b", "-c", "x.c"]
+        );
+    }
+
+    #[test]
+    fn test_prune_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::File::create(dir.path().join("present.c")).unwrap();
+
+        let directory = dir.path().to_string_lossy();
+        let entry = |file: &str| entry(&directory, file);
+        let mut commands = vec![entry("present.c"), entry("missing.c")];
+
+        assert_eq!(prune_missing(&mut commands), 1);
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].file, "present.c");
+    }
+
+    #[test]
+    fn test_inject_flags() {
+        let mut commands = vec![
+            CompileCommand {
+                directory: String::from("/tmp"),
+                file: String::from("a.c"),
+                command: None,
+                arguments: Some(vec![String::from("gcc"), String::from("a.c")]),
+                output: None,
+            },
+            CompileCommand {
+                directory: String::from("/tmp"),
+                file: String::from("a.c"),
+                command: Some(String::from("gcc a.c")),
+                arguments: None,
+                output: None,
+            },
+        ];
+
+        inject_flags(&mut commands, &["-fsanitize=address", "-DFOO"]);
+
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap(),
+            &vec!["gcc", "a.c", "-fsanitize=address", "-DFOO"]
+        );
+        assert_eq!(
+            commands[1].command.as_deref(),
+            Some("gcc a.c -fsanitize=address -DFOO")
+        );
+    }
+
+    #[test]
+    fn test_prepend_includes() {
+        let entry = |arguments: &[&str]| arguments_entry("/src", "a.c", arguments);
+        let dirs = [PathBuf::from("/sdk/include"), PathBuf::from("/my sdk/inc")];
+
+        let cmd = entry(&["gcc", "-O2", "-isystem", "/usr/x", "-Ia", "-c", "a.c"]);
+        assert_eq!(
+            cmd.with_prepended_includes(&dirs).arguments.unwrap(),
+            [
+                "gcc",
+                "-O2",
+                "-I/sdk/include",
+                "-I/my sdk/inc",
+                "-isystem",
+                "/usr/x",
+                "-Ia",
+                "-c",
+                "a.c"
+            ]
+        );
+
+        // Without include flags the directories go right after the compiler
+        let cmd = entry(&["gcc", "-c", "a.c"]).to_command_style();
+        assert_eq!(
+            cmd.with_prepended_includes(&dirs).command.as_deref(),
+            Some("gcc -I/sdk/include '-I/my sdk/inc' -c a.c")
+        );
+    }
+
+    #[test]
+    fn test_replace_compiler() {
+        let mut commands = vec![
+            CompileCommand {
+                directory: String::from("/src"),
+                file: String::from("a.c"),
+                command: None,
+                arguments: Some(["gcc", "-O2", "-c", "a.c"].map(String::from).to_vec()),
+                output: None,
+            },
+            CompileCommand {
+                directory: String::from("/src"),
+                file: String::from("b.c"),
+                command: Some(String::from("LANG=C gcc -DMSG='a b' -c b.c")),
+                arguments: None,
+                output: None,
+            },
+        ];
+
+        replace_compiler_all(&mut commands, "arm-linux-gnueabihf-gcc");
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap(),
+            &["arm-linux-gnueabihf-gcc", "-O2", "-c", "a.c"]
+        );
+        assert_eq!(
+            commands[1].command.as_deref(),
+            Some("LANG=C arm-linux-gnueabihf-gcc -DMSG='a b' -c b.c")
+        );
+    }
+
+    #[test]
+    fn test_normalize_path_separators() {
+        let mut cmd = CompileCommand {
+            directory: String::from("C:\\Users\\project"),
+            file: String::from("src\\foo.c"),
+            command: None,
+            arguments: Some(
+                [
+                    "cl.exe",
+                    "/DNAME=a\\b",
+                    "C:\\Users\\project\\src\\foo.c",
+                    "\\\\server\\share\\inc",
+                ]
+                .map(String::from)
+                .to_vec(),
+            ),
+            output: None,
+        };
+
+        cmd.normalize_path_separators();
+        assert_eq!(cmd.directory, "C:/Users/project");
+        assert_eq!(cmd.file, "src/foo.c");
+        assert_eq!(
+            cmd.arguments.as_ref().unwrap(),
+            &[
+                "cl.exe",
+                "/DNAME=a\\b",
+                "C:/Users/project/src/foo.c",
+                "//server/share/inc"
+            ]
+        );
+
+        let mut cmd = CompileCommand {
+            command: Some(String::from("cl.exe /c C:\\src\\foo.c")),
+            arguments: None,
+            ..cmd
+        };
+        cmd.normalize_path_separators();
+        assert_eq!(cmd.command.as_deref(), Some("cl.exe /c C:/src/foo.c"));
+    }
+
+    #[test]
+    fn test_normalize_compilers() {
+        let mut commands = vec![
+            CompileCommand {
+                directory: String::from("/src"),
+                file: String::from("a.c"),
+                command: None,
+                arguments: Some(
+                    ["/opt/bin/x86_64-none-linux-gcc", "-O2", "-c", "a.c"]
+                        .map(String::from)
+                        .to_vec(),
+                ),
+                output: None,
+            },
+            CompileCommand {
+                directory: String::from("/src"),
+                file: String::from("b.cpp"),
+                command: Some(String::from("LANG=C arm-none-eabi-g++-12.2 -c b.cpp")),
+                arguments: None,
+                output: None,
+            },
+        ];
+
+        normalize_compilers(&mut commands);
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap(),
+            &["gcc", "-O2", "-c", "a.c"]
+        );
+        assert_eq!(commands[1].command.as_deref(), Some("LANG=C g++ -c b.cpp"));
+
+        for (compiler, expected) in [
+            ("clang-cl.exe", "clang-cl"),
+            ("clang-14", "clang"),
+            ("C:\\VC\\bin\\cl.exe", "cl"),
+            ("/usr/bin/icc", "icc"),
+        ] {
+            assert_eq!(normalize_compiler_name(compiler), expected);
+        }
+    }
+
+    #[test]
+    fn test_map_output_extensions() {
+        let mut commands = vec![
+            CompileCommand {
+                directory: String::from("/src"),
+                file: String::from("foo.c"),
+                command: None,
+                arguments: Some(
+                    ["gcc", "-c", "foo.c", "-o", "foo.o"]
+                        .map(String::from)
+                        .to_vec(),
+                ),
+                output: Some(String::from("foo.o")),
+            },
+            CompileCommand {
+                directory: String::from("/src"),
+                file: String::from("bar.c"),
+                command: Some(String::from("gcc -c bar.c -o bar.o")),
+                arguments: None,
+                output: None,
+            },
+        ];
+
+        map_output_extensions(&mut commands, &[(String::from("o"), String::from("obj"))]);
+        assert_eq!(commands[0].output.as_deref(), Some("foo.obj"));
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap(),
+            &["gcc", "-c", "foo.c", "-o", "foo.obj"]
+        );
+        assert_eq!(
+            commands[1].command.as_deref(),
+            Some("gcc -c bar.c -o bar.obj")
+        );
+    }
+
+    #[test]
+    fn test_prepend_env_vars() {
+        let mut commands = vec![
+            CompileCommand {
+                directory: String::from("/tmp"),
+                file: String::from("a.c"),
+                command: Some(String::from("clang -c a.c")),
+                arguments: None,
+                output: None,
+            },
+            CompileCommand {
+                directory: String::from("/tmp"),
+                file: String::from("a.c"),
+                command: None,
+                arguments: Some(vec![String::from("clang"), String::from("a.c")]),
+                output: None,
+            },
+        ];
+        let arguments_style = commands[1].clone();
+
+        let env_vars = [
+            (String::from("SDKROOT"), String::from("/opt/sdk")),
+            (String::from("SYSROOT"), String::from("/opt/my root")),
+        ];
+        prepend_env_vars(&mut commands, &env_vars);
+
+        assert_eq!(
+            commands[0].command.as_deref(),
+            Some("SDKROOT=/opt/sdk SYSROOT='/opt/my root' clang -c a.c")
+        );
+        assert_eq!(commands[0].compiler(), Some("clang"));
+        assert_eq!(commands[1], arguments_style);
+    }
 }
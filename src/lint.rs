@@ -0,0 +1,168 @@
+use crate::CompileCommand;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Kind of potential problem found in a database entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    /// `file` is absolute but `directory` is relative
+    RelativeDirectory,
+
+    /// The same source file appears in more than one entry
+    DuplicateFile,
+
+    /// The compiler binary could not be found
+    CompilerNotFound,
+
+    /// `output` does not look like an object or assembly file
+    UnexpectedOutput,
+}
+
+/// A potential problem with a single database entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintWarning {
+    /// Index of the entry in the database
+    pub index: usize,
+
+    /// The entry's `file` field
+    pub file: String,
+
+    pub kind: LintKind,
+
+    /// Human-readable description of the problem
+    pub message: String,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry {} ({}): {}", self.index, self.file, self.message)
+    }
+}
+
+/// Output file extensions expected from a compile step
+const OUTPUT_EXTENSIONS: &[&str] = &["o", "obj", "s"];
+
+/// Audit a database for suspicious entries without modifying it
+pub fn lint_commands(commands: &[CompileCommand]) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for (index, cmd) in commands.iter().enumerate() {
+        let mut warn = |kind, message: String| {
+            warnings.push(LintWarning {
+                index,
+                file: cmd.file.clone(),
+                kind,
+                message,
+            })
+        };
+
+        let directory = Path::new(&cmd.directory);
+        if Path::new(&cmd.file).is_absolute() && !directory.is_absolute() {
+            warn(
+                LintKind::RelativeDirectory,
+                format!(
+                    "file is absolute but directory '{}' is relative",
+                    cmd.directory
+                ),
+            );
+        }
+
+        let unit = directory.join(&cmd.file).to_string_lossy().into_owned();
+        if let Some(first) = seen.get(&unit) {
+            let identical = commands[*first].command == cmd.command
+                && commands[*first].arguments == cmd.arguments;
+            warn(
+                LintKind::DuplicateFile,
+                format!(
+                    "source file also appears in entry {first} ({} command)",
+                    if identical { "identical" } else { "different" }
+                ),
+            );
+        } else {
+            seen.insert(unit, index);
+        }
+
+        if let Some(compiler) = cmd.compiler() {
+            if !compiler_exists(compiler, directory) {
+                warn(
+                    LintKind::CompilerNotFound,
+                    format!("compiler '{compiler}' not found"),
+                );
+            }
+        }
+
+        if let Some(output) = cmd.output.as_deref() {
+            let extension = Path::new(output)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase());
+            if !extension.is_some_and(|ext| OUTPUT_EXTENSIONS.contains(&ext.as_str())) {
+                warn(
+                    LintKind::UnexpectedOutput,
+                    format!("output '{output}' is not an object or assembly file"),
+                );
+            }
+        }
+    }
+
+    warnings
+}
+
+/// Path-like compilers are checked relative to the entry's directory, plain
+/// names are looked up on PATH
+fn compiler_exists(compiler: &str, directory: &Path) -> bool {
+    let path = Path::new(compiler);
+    if path.components().count() > 1 || path.is_absolute() {
+        directory.join(path).exists()
+    } else {
+        which::which(compiler).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(directory: &str, file: &str, compiler: &str, output: Option<&str>) -> CompileCommand {
+        CompileCommand {
+            directory: String::from(directory),
+            file: String::from(file),
+            command: None,
+            arguments: Some(vec![
+                String::from(compiler),
+                String::from("-c"),
+                String::from(file),
+            ]),
+            output: output.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_lint_commands() {
+        let compiler = std::env::current_exe().unwrap();
+        let compiler = compiler.to_str().unwrap();
+
+        let commands = vec![
+            entry("/src", "a.c", compiler, Some("a.o")),
+            entry("build", "/src/b.c", compiler, None),
+            entry("/src", "a.c", compiler, Some("a.o")),
+            entry("/src", "c.c", "/nonexistent/bin/cc", None),
+            entry("/src", "d.c", compiler, Some("d.d")),
+        ];
+
+        let kinds: Vec<_> = lint_commands(&commands)
+            .into_iter()
+            .map(|warning| (warning.index, warning.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (1, LintKind::RelativeDirectory),
+                (2, LintKind::DuplicateFile),
+                (3, LintKind::CompilerNotFound),
+                (4, LintKind::UnexpectedOutput),
+            ]
+        );
+    }
+}
@@ -1,11 +1,22 @@
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use compiledb::{CompileDbError, Config};
-use std::io::BufRead;
-use std::path::PathBuf;
-extern crate env_logger;
-extern crate log;
-use log::info;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use compiledb::meson_importer::MesonImporter;
+use compiledb::parser::ParseStats;
+use compiledb::scons_parser::SconsParser;
+use compiledb::tup_parser::TupParser;
+use compiledb::writer::StreamingJsonWriter;
+use compiledb::xcode_parser::XcodeParser;
+use compiledb::{CompileCommand, CompileDbError, Config, LogFormat};
+use notify::Watcher;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long the build log must stay unchanged before `--watch` regenerates
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -14,22 +25,63 @@ struct Cli {
     #[arg(short = 'p', long = "parse")]
     build_log: Option<PathBuf>,
 
+    /// Build system that wrote the log: make (default), xcode, tup or scons
+    #[arg(long = "log-format", value_name = "FORMAT")]
+    log_format: Option<LogFormat>,
+
     /// Output file path
     #[arg(short, long, default_value = "compile_commands.json")]
     output: PathBuf,
 
-    /// Initial build directory
+    /// Output format: json (array) or jsonl (one entry per line)
+    #[arg(long = "format", value_name = "FORMAT")]
+    format: Option<compiledb::OutputFormat>,
+
+    /// Initial build directory ($COMPILEDB_BUILD_DIR takes precedence, then this flag, then the current directory)
     #[arg(short = 'd', long = "build-dir")]
     build_dir: Option<PathBuf>,
 
+    /// Use build/, out/ or cmake-build-*/ as the build dir if it holds a Makefile or build.ninja
+    #[arg(long = "auto-build-dir")]
+    auto_build_dir: bool,
+
     /// Regular expressions to exclude files
     #[arg(short = 'e', long = "exclude")]
     exclude: Vec<String>,
 
+    /// Only keep files matching one of these regular expressions
+    #[arg(long = "include-pattern")]
+    include_pattern: Vec<String>,
+
+    /// Regular expressions to exclude build directories
+    #[arg(long = "exclude-dir")]
+    exclude_dir: Vec<String>,
+
+    /// Flag to pass to make for the dry run (repeatable, replaces the default -B -n -k -w)
+    #[arg(
+        short = 'f',
+        long = "make-flag",
+        visible_alias = "make-flags",
+        allow_hyphen_values = true
+    )]
+    make_flag: Vec<String>,
+
+    /// Set NAME=VALUE in make's environment (repeatable)
+    #[arg(long = "make-env", value_name = "NAME=VALUE", value_parser = parse_key_value)]
+    make_env: Vec<(String, String)>,
+
+    /// Run make with an empty environment, apart from --make-env
+    #[arg(long = "clear-make-env")]
+    clear_make_env: bool,
+
     /// Skip actual build
     #[arg(short = 'n', long = "no-build")]
     no_build: bool,
 
+    /// Print the database to stdout instead of writing the output file
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
     /// Enable verbose output (-v for info, -vv for debug level messages)
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
@@ -38,6 +90,14 @@ struct Cli {
     #[arg(short = 'S', long = "no-strict")]
     no_strict: bool,
 
+    /// Keep commands whose source file does not exist, but warn about them
+    #[arg(long = "warn-missing")]
+    warn_missing: bool,
+
+    /// Also check that -include/-imacros files exist (not with --no-strict)
+    #[arg(long = "strict-includes")]
+    strict_includes: bool,
+
     /// Add predefined compiler macros
     #[arg(short = 'm', long = "macros")]
     macros: Vec<String>,
@@ -46,28 +106,200 @@ struct Cli {
     #[arg(short = 'c', long = "command-style")]
     command_style: bool,
 
+    /// Also write the other style to <output stem>.command.json (or .arguments.json)
+    #[arg(long = "emit-both")]
+    emit_both: bool,
+
     /// Use full compiler path
     #[arg(long = "full-path")]
     full_path: bool,
 
+    /// Only include commands from these compiler binaries (repeatable)
+    #[arg(long = "compiler-filter", value_name = "NAME")]
+    compiler_filter: Vec<String>,
+
+    /// Only include translation units in this language: c, cpp, asm, objc, cuda (repeatable)
+    #[arg(long = "lang", value_name = "LANG")]
+    lang: Vec<compiledb::Language>,
+
     /// Regular expressions to find compile commands
-    #[arg(
-        long = "regex-compile",
-        default_value = r"(?:[^/]*/)*(gcc|clang|cc|g\+\+|c\+\+|clang\+\+|cl)(?:-[0-9\.]+)?(?:\s|$)"
-    )]
+    #[arg(long = "regex-compile", default_value = compiledb::DEFAULT_REGEX_COMPILE)]
     regex_compile: String,
 
+    /// Regular expression for a launcher prefix to strip before finding the compiler
+    #[arg(long = "compiler-wrapper-regex", value_name = "RE")]
+    compiler_wrapper_regex: Option<String>,
+
     /// Regular expressions to find source files
+    #[arg(long = "regex-file", default_value = compiledb::DEFAULT_REGEX_FILE)]
+    regex_file: String,
+
+    /// Additional source file extensions to recognize (e.g. ixx)
+    #[arg(long = "source-ext", visible_alias = "ext")]
+    source_ext: Vec<String>,
+
+    /// Strip dependency-generation flags (-MD, -MMD, -MF, ...) from the output
+    #[arg(long = "strip-deps", visible_alias = "strip-dep-flags")]
+    strip_deps: bool,
+
+    /// Remove repeated -I/-isystem directories within each command
+    #[arg(long = "dedup-includes")]
+    dedup_includes: bool,
+
+    /// Replace @file arguments with the contents of the response file
+    #[arg(long = "expand-response-files")]
+    expand_response_files: bool,
+
+    /// Include directory to insert before all existing include flags (repeatable)
+    #[arg(long = "prepend-include", value_name = "DIR")]
+    prepend_include: Vec<PathBuf>,
+
+    /// Also write a JSON map from include directories to the translation units using them
+    #[arg(long = "emit-header-map", value_name = "PATH")]
+    emit_header_map: Option<PathBuf>,
+
+    /// Write the compiler as its bare name (arm-none-eabi-gcc-12 becomes gcc)
+    #[arg(long = "normalize-compiler")]
+    normalize_compiler: bool,
+
+    /// Write this compiler in place of the one found in the build log
+    #[arg(long = "replace-compiler", value_name = "NAME")]
+    replace_compiler: Option<String>,
+
+    /// Rewrite the extension of output files, e.g. o=obj (repeatable)
+    #[arg(long = "output-extension-map", value_name = "FROM=TO", value_parser = parse_key_value)]
+    output_extension_map: Vec<(String, String)>,
+
+    /// Extra flag to append to every compile command (repeatable)
+    #[arg(long = "inject-flag", allow_hyphen_values = true)]
+    inject_flag: Vec<String>,
+
+    /// Make all file paths relative to this root, which also becomes every entry's directory
+    #[arg(long = "relative-to", value_name = "PATH")]
+    relative_to: Option<PathBuf>,
+
+    /// Write this directory for every entry, making file paths absolute first
+    #[arg(long = "working-dir-override", value_name = "PATH")]
+    working_dir_override: Option<PathBuf>,
+
+    /// Write / instead of \ in directory, file and Windows path arguments
+    #[arg(long = "normalize-separators")]
+    normalize_separators: bool,
+
+    /// Exit with an error if fewer than N compile commands are found
+    #[arg(long = "min-commands", value_name = "N")]
+    min_commands: Option<usize>,
+
+    /// Exit with an error if no compile commands are found (--min-commands 1)
+    #[arg(long = "error-on-empty")]
+    error_on_empty: bool,
+
+    /// Stop with an error after N consecutive unrecognized lines
+    #[arg(long = "max-errors", value_name = "N")]
+    max_errors: Option<usize>,
+
+    /// Print parse statistics to stderr
+    #[arg(long = "stats")]
+    stats: bool,
+
+    /// Sort entries by file and directory for reproducible output
+    #[arg(long = "sort")]
+    sort: bool,
+
+    /// Merge new entries into the existing output file instead of replacing it
+    #[arg(long = "merge")]
+    merge: bool,
+
+    /// Add new entries to the existing output file, keeping the newest entry per file
+    #[arg(long = "append")]
+    append: bool,
+
+    /// Drop entries whose source file no longer exists (not with --no-strict)
+    #[arg(long = "prune")]
+    prune: bool,
+
+    /// Check every entry against the compilation database spec before writing
+    #[arg(long = "validate")]
+    validate: bool,
+
+    /// Lines of the build's stderr to show when it fails [default: 20]
+    #[arg(long = "error-context-lines", value_name = "N")]
+    error_context_lines: Option<usize>,
+
+    /// Drop entries that exactly repeat an earlier entry
+    #[arg(long = "hash-dedup")]
+    hash_dedup: bool,
+
+    /// Keep one entry per source file: first, last or most-flags
+    #[arg(long = "dedup", value_name = "STRATEGY")]
+    dedup: Option<compiledb::MergeStrategy>,
+
+    /// Keep parsing past lines that are not valid UTF-8 instead of failing
+    #[arg(long = "keep-going")]
+    keep_going: bool,
+
+    /// Substitute $VAR and ${VAR} in parsed file paths and directories
+    #[arg(long = "expand-env")]
+    expand_env: bool,
+
+    /// Substitute $(CURDIR) and ${CURDIR} with make's working directory
+    #[arg(long = "expand-make-vars")]
+    expand_make_vars: bool,
+
+    /// Rewrite path prefixes in include, source and output paths (repeatable)
+    #[arg(long = "rewrite-path", value_name = "FROM=TO", value_parser = parse_key_value)]
+    rewrite_path: Vec<(String, String)>,
+
+    /// Prepend NAME=VALUE to each command (command style only, repeatable)
+    #[arg(long = "env-var", value_name = "NAME=VALUE", value_parser = parse_key_value)]
+    env_var: Vec<(String, String)>,
+
+    /// Also treat this binary as a C compiler [default: $CC]
+    #[arg(long = "use-cc", value_name = "CC")]
+    use_cc: Option<String>,
+
+    /// Also treat this binary as a C++ compiler [default: $CXX]
+    #[arg(long = "use-c++", value_name = "CXX")]
+    use_cxx: Option<String>,
+
+    /// Report parse progress on stderr, as a spinner while make runs
+    /// (auto: only when stderr is a terminal)
     #[arg(
-        long = "regex-file",
-        default_value = r"\s-c\s+(\S+\.(c|cpp|cc|cxx|c\+\+|s|m|mm|cu))\s+-o\s"
+        long = "progress",
+        value_name = "WHEN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto"
     )]
-    regex_file: String,
+    progress: Option<ProgressWhen>,
+
+    /// Keep running and regenerate the database whenever the --parse log changes
+    #[arg(long = "watch")]
+    watch: bool,
+
+    /// Load settings from a TOML (or .json) file; command-line flags override its values
+    #[arg(
+        long = "config-file",
+        visible_alias = "load-config",
+        value_name = "PATH"
+    )]
+    config_file: Option<PathBuf>,
+
+    /// Save the effective settings (config file plus flags) to a TOML or JSON file
+    #[arg(long = "save-config", value_name = "PATH")]
+    save_config: Option<PathBuf>,
 
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+/// When to print progress reports
+#[derive(Clone, Copy, ValueEnum)]
+enum ProgressWhen {
+    Auto,
+    Always,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Run make and generate compilation database
@@ -76,122 +308,629 @@ enum Commands {
         #[arg(trailing_var_arg = true)]
         args: Vec<String>,
     },
+    /// Run xcodebuild and generate compilation database
+    Xcode {
+        /// Arguments to pass to xcodebuild
+        #[arg(trailing_var_arg = true)]
+        args: Vec<String>,
+    },
+    /// Run tup monitor and generate compilation database
+    Tup {
+        /// Targets to pass to tup
+        #[arg(trailing_var_arg = true)]
+        args: Vec<String>,
+    },
+    /// Run scons and generate compilation database
+    Scons {
+        /// Arguments to pass to scons
+        #[arg(trailing_var_arg = true)]
+        args: Vec<String>,
+    },
+    /// Import compile commands from `meson introspect --targets`
+    Meson {
+        /// Meson build directory [default: the global --build-dir]
+        #[arg(long = "build-dir")]
+        build_dir: Option<PathBuf>,
+
+        /// Only import these targets
+        targets: Vec<String>,
+    },
+    /// Write a Makefile that rebuilds the objects recorded in a database
+    ExportMakefile {
+        /// Database to export [default: the output file]
+        database: Option<PathBuf>,
+
+        /// Makefile to write
+        #[arg(short = 'm', long = "makefile", default_value = "compile_commands.mk")]
+        makefile: PathBuf,
+    },
+    /// Convert a database to bear's schema, with `cmd` in place of `command`/`arguments`
+    BearExport {
+        /// Database to convert [default: the output file]
+        database: Option<PathBuf>,
+
+        /// bear-format file to write
+        #[arg(short = 'b', long = "bear-file", default_value = "bear.json")]
+        bear_file: PathBuf,
+    },
+    /// Report suspicious entries in an existing compilation database
+    Lint {
+        /// Database to check [default: the output file]
+        database: Option<PathBuf>,
+
+        /// Exit with an error if any issues are found
+        #[arg(long = "strict")]
+        strict: bool,
+    },
+}
+
+/// Use the command-line values if any were given, otherwise the base ones
+fn non_empty_or<T>(cli: Vec<T>, base: Vec<T>) -> Vec<T> {
+    if cli.is_empty() { base } else { cli }
+}
+
+/// Parse a `KEY=VALUE` command-line value
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{value}'"))
+}
+
+/// Find the build directory for `--auto-build-dir`: the first of `build`,
+/// `out` and `cmake-build-*` (in name order) under `root` that holds a
+/// Makefile or build.ninja
+fn find_build_dir(root: &Path) -> Option<PathBuf> {
+    let mut cmake_dirs: Vec<PathBuf> = std::fs::read_dir(root)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("cmake-build-"))
+        })
+        .collect();
+    cmake_dirs.sort();
+
+    [root.join("build"), root.join("out")]
+        .into_iter()
+        .chain(cmake_dirs)
+        .find(|dir| {
+            ["GNUmakefile", "makefile", "Makefile", "build.ninja"]
+                .iter()
+                .any(|name| dir.join(name).is_file())
+        })
+}
+
+/// Apply output post-processing and write the compilation database
+fn write_database(commands: Vec<CompileCommand>, config: &Config) -> Result<(), CompileDbError> {
+    let commands = compiledb::finalize_database(commands, config)?;
+    check_min_commands(commands.len(), config)?;
+
+    if config.dry_run {
+        print_dry_run(&commands, config)?;
+        return Ok(());
+    }
+
+    let file = create_file(&config.output_file, "output file")?;
+    compiledb::write_commands(BufWriter::new(file), &commands, config.format)?;
+
+    info!(
+        "Wrote compilation database to {}",
+        config.output_file.display()
+    );
+
+    if config.emit_both {
+        // An entry must have exactly one of the two, so the other style gets its own file
+        let (style, other): (_, Vec<_>) = if config.command_style {
+            (
+                "arguments",
+                commands
+                    .iter()
+                    .map(CompileCommand::to_arguments_style)
+                    .collect(),
+            )
+        } else {
+            (
+                "command",
+                commands
+                    .iter()
+                    .map(CompileCommand::to_command_style)
+                    .collect(),
+            )
+        };
+        let path = style_variant_path(&config.output_file, style);
+        let file = create_file(&path, "output file")?;
+        compiledb::write_commands(BufWriter::new(file), &other, config.format)?;
+        info!(
+            "Wrote {style}-style compilation database to {}",
+            path.display()
+        );
+    }
+
+    if let Some(path) = config.emit_header_map.as_ref() {
+        let file = create_file(path, "header map")?;
+        serde_json::to_writer_pretty(file, &compiledb::header_map(&commands))
+            .map_err(CompileDbError::Json)?;
+        info!("Wrote header map to {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Create `path` for writing, naming `what` it is in the error
+fn create_file(path: &Path, what: &str) -> Result<File, CompileDbError> {
+    File::create(path)
+        .with_context(|| format!("Failed to create {what}: {}", path.display()))
+        .map_err(|e| CompileDbError::Io(std::io::Error::other(e)))
+}
+
+/// The path of the `--emit-both` database in `style`, e.g.
+/// `compile_commands.command.json` next to `compile_commands.json`
+fn style_variant_path(output: &Path, style: &str) -> PathBuf {
+    let mut name = output.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!(".{style}"));
+    if let Some(ext) = output.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    output.with_file_name(name)
+}
+
+/// Whether the whole database must be collected before writing, rather than streamed
+fn needs_buffering(config: &Config) -> bool {
+    config.dry_run
+        || config.sort
+        || config.merge
+        || config.append
+        || config.prune
+        || config.hash_dedup
+        || config.dedup.is_some()
+        || config.validate
+        || config.emit_both
+        || config.emit_header_map.is_some()
+}
+
+/// Stream the compilation database through `produce`, writing to a temporary
+/// file that replaces the output only once the database is complete. For
+/// `live` input, such as a FIFO, entries go straight to the output file and
+/// are flushed one by one so it can be followed while the build runs.
+fn stream_database<F>(config: &Config, live: bool, produce: F) -> Result<usize, CompileDbError>
+where
+    F: FnOnce(&mut StreamingJsonWriter<BufWriter<File>>) -> Result<(), CompileDbError>,
+{
+    // A minimum is only known to be met at the end, so the existing output
+    // must not be overwritten entry by entry
+    let live = live && config.min_commands.is_none();
+    let write_path = if live {
+        config.output_file.clone()
+    } else {
+        let mut tmp_name = config.output_file.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        PathBuf::from(tmp_name)
+    };
+
+    let file = File::create(&write_path)
+        .with_context(|| format!("Failed to create output file: {}", write_path.display()))
+        .map_err(|e| CompileDbError::Io(std::io::Error::other(e)))?;
+    let mut writer = StreamingJsonWriter::with_format(BufWriter::new(file), config.format)
+        .flush_each_entry(live);
+
+    let result = writer
+        .begin()
+        .and_then(|_| produce(&mut writer))
+        .and_then(|_| writer.finish())
+        .and_then(|_| check_min_commands(writer.entries_written(), config));
+    if let Err(e) = result {
+        if !live {
+            let _ = std::fs::remove_file(&write_path);
+        }
+        return Err(e);
+    }
+    if !live {
+        std::fs::rename(&write_path, &config.output_file)?;
+    }
+
+    info!(
+        "Wrote compilation database to {}",
+        config.output_file.display()
+    );
+    Ok(writer.entries_written())
+}
+
+/// Print the database that would be written to stdout, followed by a summary
+/// on stderr of how it differs from the existing output file
+fn print_dry_run(commands: &[CompileCommand], config: &Config) -> Result<(), CompileDbError> {
+    let mut stdout = std::io::stdout().lock();
+    compiledb::write_commands(&mut stdout, commands, config.format)?;
+    if config.format == compiledb::OutputFormat::Json {
+        writeln!(stdout)?;
+    }
+
+    eprintln!(
+        "Would write {} entries to {}",
+        commands.len(),
+        config.output_file.display()
+    );
+
+    if config.output_file.exists() {
+        let existing = compiledb::load_database(&config.output_file)?;
+        let diff = compiledb::diff_databases(&existing, commands);
+        if diff.is_empty() {
+            eprintln!("No changes compared to the existing database");
+        }
+        for unit in &diff.added {
+            eprintln!("  added:   {unit}");
+        }
+        for unit in &diff.removed {
+            eprintln!("  removed: {unit}");
+        }
+        for unit in &diff.updated {
+            eprintln!("  updated: {unit}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Print parse statistics to stderr when `--stats` is given
+fn report_stats(stats: &ParseStats, config: &Config) {
+    if config.print_stats {
+        eprintln!("{stats}");
+    } else {
+        info!(
+            "Parse stats: {} lines, {} commands, {} excluded, {} missing files",
+            stats.lines_processed, stats.commands_found, stats.excluded, stats.missing_files
+        );
+    }
+}
+
+/// Fail when the final database holds fewer compile commands than required
+/// by `--min-commands`
+fn check_min_commands(found: usize, config: &Config) -> Result<(), CompileDbError> {
+    match config.min_commands {
+        Some(expected) if found < expected => {
+            Err(CompileDbError::TooFewCommands { found, expected })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parse the build log (or stdin) and write the compilation database
+fn parse_build_output(config: &Config) -> Result<(), CompileDbError> {
+    // Parse from file or stdin. A log that is not a regular file, like a
+    // FIFO the build is teed into, is read live as it is written.
+    let mut live = false;
+    let reader: Box<dyn BufRead> = if let Some(log_file) = config.build_log.as_ref() {
+        info!("Parsing build log file: {}", log_file.display());
+        let file = std::fs::File::open(log_file)
+            .with_context(|| format!("Failed to open build log file: {}", log_file.display()))
+            .map_err(|e| CompileDbError::Io(std::io::Error::other(e)))?;
+        live = !file.metadata()?.is_file();
+        Box::new(BufReader::new(file))
+    } else {
+        info!("Reading build output from stdin...");
+        Box::new(BufReader::new(std::io::stdin()))
+    };
+
+    // Logs of other build systems go through their own parsers
+    let commands = match config.log_format {
+        LogFormat::Make => return parse_make_output(reader, live, config),
+        LogFormat::Xcode => XcodeParser::new(config)?.parse_reader(reader, config)?,
+        LogFormat::Tup => TupParser::new(config)?.parse_reader(reader, config)?,
+        LogFormat::Scons => SconsParser::new(config)?.parse_reader(reader, config)?,
+    };
+    write_database(commands, config)
+}
+
+/// Parse make output, streaming entries to the output file unless the whole
+/// database is needed first
+fn parse_make_output(
+    reader: impl BufRead,
+    live: bool,
+    config: &Config,
+) -> Result<(), CompileDbError> {
+    let mut parser = compiledb::parser::Parser::new(config)?;
+    if needs_buffering(config) {
+        let commands = parser.parse_reader(reader, config)?;
+        write_database(commands, config)?;
+    } else {
+        // Stream entries to the output file as they are parsed
+        stream_database(config, live, |writer| {
+            parser.for_each_command(reader, config, |mut cmd| {
+                info!("  Command: file={}, dir={}", cmd.file, cmd.directory);
+                compiledb::apply_output_options(std::slice::from_mut(&mut cmd), config);
+                writer.write_entry(&cmd)
+            })
+        })?;
+    }
+
+    let stats = parser.stats();
+    report_stats(stats, config);
+    Ok(())
+}
+
+/// Regenerate the database each time `log_file` changes, until interrupted.
+/// Bursts of changes are handled once the log has been quiet for
+/// [`WATCH_DEBOUNCE`], and failures are reported without stopping the watch.
+fn watch_build_log(log_file: &Path, config: &Config) -> Result<(), CompileDbError> {
+    let watch_error = |e: notify::Error| CompileDbError::Io(std::io::Error::other(e));
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(watch_error)?;
+
+    // Watch the directory so a log that is recreated, not just appended to, is still seen
+    let log_file = std::path::absolute(log_file)?;
+    let dir = log_file.parent().unwrap_or(Path::new("/"));
+    watcher
+        .watch(dir, notify::RecursiveMode::NonRecursive)
+        .map_err(watch_error)?;
+    info!("Watching {} for changes", log_file.display());
+
+    let changes_log = |event: &notify::Result<notify::Event>| match event {
+        Ok(event) => {
+            matches!(
+                event.kind,
+                notify::EventKind::Create(_) | notify::EventKind::Modify(_)
+            ) && event.paths.contains(&log_file)
+        }
+        Err(_) => false,
+    };
+    while let Ok(event) = rx.recv() {
+        if !changes_log(&event) {
+            continue;
+        }
+        // Other files in the directory (like the output) must not delay the rebuild
+        let mut last_change = Instant::now();
+        loop {
+            match rx.recv_timeout(WATCH_DEBOUNCE.saturating_sub(last_change.elapsed())) {
+                Ok(event) if changes_log(&event) => last_change = Instant::now(),
+                Ok(_) => {}
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => break,
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+
+        info!("{} changed, regenerating", log_file.display());
+        if let Err(e) = parse_build_output(config) {
+            warn!("Failed to regenerate the compilation database: {e}");
+        }
+    }
+    Ok(())
 }
 
 fn run() -> Result<(), CompileDbError> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    // Settings from --config-file form the base that command-line flags override
+    let base = match cli.config_file.as_deref() {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+
+    // Only search when no build directory was given explicitly
+    let auto_build_dir = (cli.auto_build_dir
+        && cli.build_dir.is_none()
+        && compiledb::build_dir_from_env().is_none())
+    .then(|| std::env::current_dir().map(|root| find_build_dir(&root)))
+    .transpose()?;
+
+    let config = Config {
+        build_log: cli.build_log.or(base.build_log),
+        log_format: cli.log_format.unwrap_or(base.log_format),
+        output_file: if from_cli("output") {
+            cli.output
+        } else {
+            base.output_file
+        },
+        format: cli.format.unwrap_or(base.format),
+        build_dir: compiledb::build_dir_from_env()
+            .or(cli.build_dir)
+            .or(auto_build_dir.clone().flatten())
+            .unwrap_or(base.build_dir),
+        exclude_patterns: non_empty_or(cli.exclude, base.exclude_patterns),
+        include_patterns: non_empty_or(cli.include_pattern, base.include_patterns),
+        exclude_dir_patterns: non_empty_or(cli.exclude_dir, base.exclude_dir_patterns),
+        // Any user-provided make flag replaces the defaults entirely
+        make_flags: non_empty_or(cli.make_flag, base.make_flags),
+        make_env: non_empty_or(cli.make_env, base.make_env),
+        clear_make_env: cli.clear_make_env || base.clear_make_env,
+        no_build: cli.no_build || base.no_build,
+        dry_run: cli.dry_run || base.dry_run,
+        verbose: cli.verbose.max(base.verbose),
+        no_strict: cli.no_strict || base.no_strict,
+        warn_missing: cli.warn_missing || base.warn_missing,
+        strict_includes: cli.strict_includes || base.strict_includes,
+        macros: non_empty_or(cli.macros, base.macros),
+        command_style: cli.command_style || base.command_style,
+        emit_both: cli.emit_both || base.emit_both,
+        full_path: cli.full_path || base.full_path,
+        compiler_filter: non_empty_or(cli.compiler_filter, base.compiler_filter),
+        regex_compile: if from_cli("regex_compile") {
+            cli.regex_compile
+        } else {
+            base.regex_compile
+        },
+        compiler_wrapper_regex: cli.compiler_wrapper_regex.or(base.compiler_wrapper_regex),
+        regex_file: if from_cli("regex_file") {
+            cli.regex_file
+        } else {
+            base.regex_file
+        },
+        source_extensions: non_empty_or(cli.source_ext, base.source_extensions),
+        strip_deps: cli.strip_deps || base.strip_deps,
+        inject_flags: non_empty_or(cli.inject_flag, base.inject_flags),
+        prepend_includes: non_empty_or(cli.prepend_include, base.prepend_includes),
+        normalize_compiler: cli.normalize_compiler || base.normalize_compiler,
+        replace_compiler: cli.replace_compiler.or(base.replace_compiler),
+        output_extension_map: non_empty_or(cli.output_extension_map, base.output_extension_map),
+        emit_header_map: cli.emit_header_map.or(base.emit_header_map),
+        relative_to: cli.relative_to.or(base.relative_to),
+        working_dir_override: cli.working_dir_override.or(base.working_dir_override),
+        normalize_separators: cli.normalize_separators || base.normalize_separators,
+        // --error-on-empty raises any lower minimum, including 0, to 1
+        min_commands: match cli.min_commands.or(base.min_commands) {
+            min if cli.error_on_empty => Some(min.unwrap_or_default().max(1)),
+            min => min,
+        },
+        max_consecutive_non_matching: cli.max_errors.or(base.max_consecutive_non_matching),
+        print_stats: cli.stats || base.print_stats,
+        sort: cli.sort || base.sort,
+        merge: cli.merge || base.merge,
+        append: cli.append || base.append,
+        error_context_lines: cli.error_context_lines.unwrap_or(base.error_context_lines),
+        hash_dedup: cli.hash_dedup || base.hash_dedup,
+        dedup: cli.dedup.or(base.dedup),
+        validate: cli.validate || base.validate,
+        prune: cli.prune || base.prune,
+        keep_going: cli.keep_going || base.keep_going,
+        expand_env_vars: cli.expand_env || base.expand_env_vars,
+        expand_make_vars: cli.expand_make_vars || base.expand_make_vars,
+        rewrite_paths: non_empty_or(cli.rewrite_path, base.rewrite_paths),
+        env_vars: non_empty_or(cli.env_var, base.env_vars),
+        cc: cli.use_cc.or(base.cc),
+        cxx: cli.use_cxx.or(base.cxx),
+        warn_no_enter_dir: base.warn_no_enter_dir,
+        filter_linker_commands: base.filter_linker_commands,
+        filter_msvc_includes: base.filter_msvc_includes,
+        languages: non_empty_or(cli.lang, base.languages),
+        dedup_includes: cli.dedup_includes || base.dedup_includes,
+        expand_response_files: cli.expand_response_files || base.expand_response_files,
+        progress: match cli.progress {
+            Some(ProgressWhen::Always) => true,
+            Some(ProgressWhen::Auto) => std::io::stderr().is_terminal(),
+            // A config file can only ask for progress, which is then auto
+            None => base.progress && std::io::stderr().is_terminal(),
+        },
+    };
 
     // Configure logging based on verbose flag
-    let log_level = match cli.verbose {
+    let log_level = match config.verbose {
         0 => "warn",
         1 => "info",
         _ => "debug",
     };
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level)).init();
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(log_level));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    match auto_build_dir {
+        Some(Some(dir)) => info!("Using build directory {}", dir.display()),
+        Some(None) => warn!(
+            "--auto-build-dir found no build directory, using {}",
+            config.build_dir.display()
+        ),
+        None => {}
+    }
 
-    let config = Config {
-        build_log: cli.build_log,
-        output_file: cli.output,
-        build_dir: cli
-            .build_dir
-            .unwrap_or_else(|| std::env::current_dir().unwrap()),
-        exclude_patterns: cli.exclude,
-        no_build: cli.no_build,
-        verbose: cli.verbose,
-        no_strict: cli.no_strict,
-        macros: cli.macros,
-        command_style: cli.command_style,
-        full_path: cli.full_path,
-        regex_compile: cli.regex_compile,
-        regex_file: cli.regex_file,
-    };
+    if !config.env_vars.is_empty() && !config.command_style {
+        warn!("--env-var only applies to command-style output (--command-style), ignoring it");
+    }
+
+    if let Some(path) = cli.save_config.as_deref() {
+        config.save(path)?;
+        info!("Saved configuration to {}", path.display());
+    }
 
     match cli.command {
         Some(Commands::Make { args }) => {
             let wrapper = compiledb::make_wrapper::MakeWrapper::new();
 
-            // First run make with -Bnwk to get compilation commands
-            let commands = wrapper.execute(&args, &config)?;
-
-            // Write compilation database
-            let file = std::fs::File::create(&config.output_file)
-                .with_context(|| {
-                    format!(
-                        "Failed to create output file: {}",
-                        config.output_file.display()
-                    )
-                })
-                .map_err(|e| CompileDbError::Io(std::io::Error::other(e)))?;
-
-            serde_json::to_writer_pretty(file, &commands).map_err(CompileDbError::Json)?;
+            // First run make with the dry-run flags to get compilation commands
+            let stats = if needs_buffering(&config) {
+                // Sorting and the dry-run summary need the whole database
+                let (commands, stats) =
+                    wrapper.execute_with_writer::<std::io::Sink>(&args, &config, None)?;
+                write_database(commands, &config)?;
+                stats
+            } else {
+                // Stream entries to the output file as make reports them
+                let mut stats = None;
+                stream_database(&config, false, |writer| {
+                    stats = Some(wrapper.execute_with_writer(&args, &config, Some(writer))?.1);
+                    Ok(())
+                })?;
+                stats.unwrap_or_default()
+            };
+            report_stats(&stats, &config);
 
-            info!(
-                "Wrote compilation database to {}",
-                config.output_file.display()
+            // Run actual build if requested
+            if !config.dry_run {
+                wrapper.run_build(&args, &config)?;
+            }
+        }
+        Some(Commands::Xcode { args }) => {
+            // xcodebuild performs the real build while printing its commands
+            let commands = compiledb::xcode_parser::run_xcodebuild(&args, &config)?;
+            write_database(commands, &config)?;
+        }
+        Some(Commands::Tup { args }) => {
+            // tup performs the real build while printing its commands
+            let commands = compiledb::tup_parser::run_tup(&args, &config)?;
+            write_database(commands, &config)?;
+        }
+        Some(Commands::Scons { args }) => {
+            // scons builds while echoing its commands (or only echoes them with -n)
+            let commands = compiledb::scons_parser::run_scons(&args, &config)?;
+            write_database(commands, &config)?;
+        }
+        Some(Commands::Meson { build_dir, targets }) => {
+            // meson introspect reads the configured build tree without building
+            let build_dir = build_dir.unwrap_or_else(|| config.build_dir.clone());
+            let commands = MesonImporter::new(build_dir).run(&targets)?;
+            write_database(commands, &config)?;
+        }
+        Some(Commands::ExportMakefile { database, makefile }) => {
+            let path = database.unwrap_or_else(|| config.output_file.clone());
+            let commands = compiledb::load_database(&path)?;
+            compiledb::export::export_makefile(&commands, &makefile)?;
+            info!("Wrote Makefile to {}", makefile.display());
+        }
+        Some(Commands::BearExport {
+            database,
+            bear_file,
+        }) => {
+            let path = database.unwrap_or_else(|| config.output_file.clone());
+            let commands = compiledb::load_database(&path)?;
+            let file = create_file(&bear_file, "bear file")?;
+            serde_json::to_writer_pretty(
+                BufWriter::new(file),
+                &compiledb::bear::bear_convert(&commands),
+            )?;
+            info!("Wrote bear database to {}", bear_file.display());
+        }
+        Some(Commands::Lint { database, strict }) => {
+            let path = database.unwrap_or_else(|| config.output_file.clone());
+            let commands = compiledb::load_database(&path)?;
+
+            let warnings = compiledb::lint::lint_commands(&commands);
+            for warning in &warnings {
+                println!("{warning}");
+            }
+            println!(
+                "{} issues found in {} entries",
+                warnings.len(),
+                commands.len()
             );
 
-            // Run actual build if requested
-            wrapper.run_build(&args, &config)?;
+            if strict && !warnings.is_empty() {
+                return Err(CompileDbError::LintFailed(warnings.len()));
+            }
         }
         None => {
-            // Parse from file or stdin
-            let mut parser = compiledb::parser::Parser::new(&config)?;
-
-            let commands = if let Some(log_file) = config.build_log.as_ref() {
-                parser.parse_file(log_file, &config)?
-            } else {
-                // Read from stdin
-                info!("Reading build output from stdin...");
-                let stdin = std::io::stdin();
-                let reader = std::io::BufReader::new(stdin);
-                let mut commands = Vec::new();
-                let mut line_count = 0;
-
-                for line in reader.lines() {
-                    line_count += 1;
-                    let line = line.map_err(CompileDbError::Io)?;
-                    let parsed_commands = parser.parse_line(&line, &config);
-                    if !parsed_commands.is_empty() {
-                        info!(
-                            "Found {} compile commands in line {}",
-                            parsed_commands.len(),
-                            line_count
-                        );
-                        for (i, cmd) in parsed_commands.iter().enumerate() {
-                            info!(
-                                "  Command {}.{}: file={}, dir={}",
-                                line_count,
-                                i + 1,
-                                cmd.file,
-                                cmd.directory
-                            );
-                        }
-                    }
-                    commands.extend(parsed_commands);
-                }
-
-                info!("Total lines processed: {line_count}");
-                info!("Total compile commands found: {}", commands.len());
-
-                commands
-            };
-
-            // Write compilation database
-            let file = std::fs::File::create(&config.output_file)
-                .with_context(|| {
-                    format!(
-                        "Failed to create output file: {}",
-                        config.output_file.display()
-                    )
-                })
-                .map_err(|e| CompileDbError::Io(std::io::Error::other(e)))?;
-
-            serde_json::to_writer_pretty(file, &commands).map_err(CompileDbError::Json)?;
-
-            info!(
-                "Wrote compilation database to {}",
-                config.output_file.display()
-            );
+            parse_build_output(&config)?;
+
+            if cli.watch {
+                let log_file = config.build_log.as_deref().ok_or_else(|| {
+                    CompileDbError::InvalidCommand(String::from("--watch requires --parse"))
+                })?;
+                watch_build_log(log_file, &config)?;
+            }
         }
     }
 
@@ -204,3 +943,31 @@ fn main() {
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex_defaults() {
+        let cli = Cli::try_parse_from(["compiledb"]).unwrap();
+        assert_eq!(cli.regex_compile, compiledb::DEFAULT_REGEX_COMPILE);
+        assert_eq!(cli.regex_file, compiledb::DEFAULT_REGEX_FILE);
+    }
+
+    #[test]
+    fn test_find_build_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        std::fs::create_dir_all(root.join("build")).unwrap();
+        std::fs::create_dir_all(root.join("out")).unwrap();
+        std::fs::create_dir_all(root.join("cmake-build-release")).unwrap();
+        std::fs::write(root.join("cmake-build-release/build.ninja"), "").unwrap();
+        assert_eq!(find_build_dir(root), Some(root.join("cmake-build-release")));
+
+        std::fs::write(root.join("build/Makefile"), "all:\n").unwrap();
+        assert_eq!(find_build_dir(root), Some(root.join("build")));
+
+        assert_eq!(find_build_dir(&root.join("out")), None);
+    }
+}
@@ -1,12 +1,15 @@
-use crate::{CompileCommand, CompileDbError, Config};
+use crate::parser::{ParseStats, decode_line};
+use crate::writer::StreamingJsonWriter;
+use crate::{CompileCommand, CompileDbError, Config, apply_output_options, expand_tilde};
+use indicatif::{ProgressBar, ProgressStyle};
 use std::{
-    io::{BufRead, BufReader},
+    collections::VecDeque,
+    io::{BufRead, BufReader, Write},
     path::PathBuf,
     process::{Command, Stdio},
+    time::Duration,
 };
-extern crate env_logger;
-extern crate log;
-use log::{debug, info};
+use tracing::{debug, info, warn};
 
 pub struct MakeWrapper {
     make_path: PathBuf,
@@ -19,25 +22,51 @@ impl MakeWrapper {
         Self { make_path }
     }
 
+    /// Use a specific `make` executable instead of the one found on PATH
+    pub fn with_make_path(make_path: impl Into<PathBuf>) -> Self {
+        Self {
+            make_path: make_path.into(),
+        }
+    }
+
     /// Execute make command and capture its output
     pub fn execute(
         &self,
         args: &[String],
         config: &Config,
     ) -> Result<Vec<CompileCommand>, CompileDbError> {
-        info!("Executing make with dry-run flags (-Bnkw)");
+        self.execute_with_writer::<std::io::Sink>(args, config, None)
+            .map(|(commands, _)| commands)
+    }
+
+    /// Execute make command, optionally streaming entries to `writer` as they
+    /// are parsed. Streamed entries have the config's output options applied
+    /// and are not included in the returned list. Also returns the parse
+    /// statistics for make's output.
+    pub fn execute_with_writer<W: Write>(
+        &self,
+        args: &[String],
+        config: &Config,
+        mut writer: Option<&mut StreamingJsonWriter<W>>,
+    ) -> Result<(Vec<CompileCommand>, ParseStats), CompileDbError> {
+        info!("Executing make with dry-run flags {:?}", config.make_flags);
         info!("Make arguments: {:?}", args);
         info!("Build directory: {}", config.build_dir.display());
 
         let mut command = Command::new(&self.make_path);
 
-        // Add standard make flags for dry run and continue on error
+        // Add make flags for dry run and continue on error. The trailing -j1
+        // overrides any -j from the arguments or MAKEFLAGS, since parallel
+        // jobs interleave the printed recipes. Only the dry run is affected;
+        // run_build keeps the requested parallelism.
         command
-            .arg("-Bnkw")
+            .args(&config.make_flags)
             .args(args)
-            .current_dir(&config.build_dir)
+            .arg("-j1")
+            .current_dir(expand_tilde(&config.build_dir))
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
+        set_make_env(&mut command, config);
 
         debug!("Executing make command: {command:?}");
 
@@ -53,22 +82,64 @@ impl MakeWrapper {
             CompileDbError::MakeError("Failed to capture make stderr".to_string())
         })?;
 
+        // Drain stderr concurrently so a full pipe can't block make
+        let stderr_reader = std::thread::spawn(move || {
+            BufReader::new(stderr)
+                .split(b'\n')
+                .collect::<Result<Vec<_>, _>>()
+        });
+
         // Create parser for the make output
         let mut parser = crate::parser::Parser::new(config)?;
         let mut commands = Vec::new();
 
+        let mut emit = |mut parsed: Vec<CompileCommand>| -> Result<(), CompileDbError> {
+            match writer.as_deref_mut() {
+                Some(writer) => {
+                    apply_output_options(&mut parsed, config);
+                    for cmd in &parsed {
+                        writer.write_entry(cmd)?;
+                    }
+                }
+                None => commands.extend(parsed),
+            }
+            Ok(())
+        };
+
         // Process stdout
+        let progress = config.progress.then(make_spinner);
         let stdout_reader = BufReader::new(stdout);
-        for line in stdout_reader.lines() {
-            let line = line.map_err(CompileDbError::Io)?;
-            commands.extend(parser.parse_line(&line, config));
+        for (index, line) in stdout_reader.split(b'\n').enumerate() {
+            let line = decode_line(line?, index + 1, config.keep_going)?;
+            emit(parser.try_parse_line(&line, config)?)?;
+            if let Some(progress) = &progress {
+                progress.set_message(format!(
+                    "{} compile commands found",
+                    parser.stats().commands_found
+                ));
+            }
+        }
+        if let Some(progress) = progress {
+            progress.finish_and_clear();
         }
 
-        // Process stderr (for warnings/errors)
-        let stderr_reader = BufReader::new(stderr);
-        for line in stderr_reader.lines() {
-            let line = line.map_err(CompileDbError::Io)?;
+        // Process stderr, since some recipes echo their commands there
+        let mut stderr_tail = VecDeque::with_capacity(config.error_context_lines);
+        let stderr_lines = stderr_reader
+            .join()
+            .map_err(|_| CompileDbError::MakeError("Failed to read make stderr".to_string()))?
+            .map_err(CompileDbError::Io)?;
+        for (index, line) in stderr_lines.into_iter().enumerate() {
+            let line = decode_line(line, index + 1, config.keep_going)?;
             debug!("Make stderr: {line}");
+            // stderr also carries compiler diagnostics, so rejected lines are
+            // only logged rather than counted against the parse
+            let parsed = parser.parse_line(&line, config).unwrap_or_else(|e| {
+                warn!("Skipping make stderr line: {e}");
+                Vec::new()
+            });
+            emit(parsed)?;
+            push_tail(&mut stderr_tail, line, config.error_context_lines);
         }
 
         // Wait for make to finish
@@ -77,7 +148,86 @@ impl MakeWrapper {
             .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
 
         if !status.success() && !config.no_build {
-            return Err(CompileDbError::MakeError("Make command failed".to_string()));
+            return Err(CompileDbError::BuildFailed {
+                exit_code: status.code(),
+                stderr_tail: stderr_tail.into(),
+            });
+        }
+
+        info!(
+            "Found {} compilation commands",
+            parser.stats().commands_found
+        );
+        Ok((commands, parser.stats().clone()))
+    }
+
+    /// Async counterpart of [`execute`](Self::execute), running make through
+    /// `tokio::process` and parsing its output without blocking the executor
+    #[cfg(feature = "tokio")]
+    pub async fn execute_async(
+        &self,
+        args: &[String],
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        use tokio::io::AsyncReadExt;
+
+        info!("Executing make with dry-run flags {:?}", config.make_flags);
+        info!("Make arguments: {:?}", args);
+
+        let mut command = tokio::process::Command::new(&self.make_path);
+        if config.clear_make_env {
+            command.env_clear();
+        }
+        let mut child = command
+            .envs(config.make_env.iter().map(|(name, value)| (name, value)))
+            .args(&config.make_flags)
+            .args(args)
+            .arg("-j1")
+            .current_dir(expand_tilde(&config.build_dir))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+
+        let stdout = child.stdout.take().ok_or_else(|| {
+            CompileDbError::MakeError("Failed to capture make stdout".to_string())
+        })?;
+        let mut stderr = child.stderr.take().ok_or_else(|| {
+            CompileDbError::MakeError("Failed to capture make stderr".to_string())
+        })?;
+
+        // Read stderr alongside stdout so a full pipe can't block make
+        let mut parser = crate::parser::Parser::new(config)?;
+        let (mut commands, stderr_bytes) = tokio::try_join!(
+            parser.parse_reader_async(tokio::io::BufReader::new(stdout), config),
+            async {
+                let mut bytes = Vec::new();
+                stderr.read_to_end(&mut bytes).await?;
+                Ok::<_, CompileDbError>(bytes)
+            },
+        )?;
+
+        let mut stderr_tail = VecDeque::with_capacity(config.error_context_lines);
+        let stderr_bytes = stderr_bytes.strip_suffix(b"\n").unwrap_or(&stderr_bytes);
+        for (index, line) in stderr_bytes.split(|&b| b == b'\n').enumerate() {
+            let line = decode_line(line.to_vec(), index + 1, config.keep_going)?;
+            debug!("Make stderr: {line}");
+            commands.extend(parser.parse_line(&line, config).unwrap_or_else(|e| {
+                warn!("Skipping make stderr line: {e}");
+                Vec::new()
+            }));
+            push_tail(&mut stderr_tail, line, config.error_context_lines);
+        }
+
+        let status = child
+            .wait()
+            .await
+            .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+        if !status.success() && !config.no_build {
+            return Err(CompileDbError::BuildFailed {
+                exit_code: status.code(),
+                stderr_tail: stderr_tail.into(),
+            });
         }
 
         info!("Found {} compilation commands", commands.len());
@@ -97,26 +247,82 @@ impl MakeWrapper {
         let mut command = Command::new(&self.make_path);
         command
             .args(args)
-            .current_dir(&config.build_dir)
+            .current_dir(expand_tilde(&config.build_dir))
             .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit());
+            .stderr(Stdio::piped());
+        set_make_env(&mut command, config);
 
         debug!("Running build command: {command:?}");
 
-        let status = command
-            .status()
+        let mut child = command
+            .spawn()
+            .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+        let stderr = child.stderr.take().ok_or_else(|| {
+            CompileDbError::MakeError("Failed to capture make stderr".to_string())
+        })?;
+
+        // Pass stderr through as it arrives, keeping its last lines for the error
+        let context_lines = config.error_context_lines;
+        let stderr_tail = std::thread::spawn(move || {
+            let mut tail = VecDeque::with_capacity(context_lines);
+            let mut out = std::io::stderr().lock();
+            for line in BufReader::new(stderr).split(b'\n') {
+                let Ok(line) = line else { break };
+                let _ = out.write_all(&line).and_then(|_| out.write_all(b"\n"));
+                let line = String::from_utf8_lossy(&line).into_owned();
+                push_tail(&mut tail, line, context_lines);
+            }
+            tail
+        });
+
+        let status = child
+            .wait()
             .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+        let stderr_tail = stderr_tail.join().unwrap_or_default();
 
         if !status.success() {
-            return Err(CompileDbError::MakeError(
-                "Build command failed".to_string(),
-            ));
+            return Err(CompileDbError::BuildFailed {
+                exit_code: status.code(),
+                stderr_tail: stderr_tail.into(),
+            });
         }
 
         Ok(())
     }
 }
 
+/// Append `line` to `tail`, keeping only the last `limit` lines of stderr
+/// for the error of a failed make run
+fn push_tail(tail: &mut VecDeque<String>, line: String, limit: usize) {
+    if limit == 0 {
+        return;
+    }
+    if tail.len() == limit {
+        tail.pop_front();
+    }
+    tail.push_back(line);
+}
+
+/// Spinner on stderr showing the elapsed time and the commands found so far
+fn make_spinner() -> ProgressBar {
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::with_template("{spinner} [{elapsed_precise}] Running make: {msg}")
+            .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+    );
+    spinner.set_message("0 compile commands found");
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+/// Apply `make_env` and `clear_make_env` to a make invocation
+fn set_make_env(command: &mut Command, config: &Config) {
+    if config.clear_make_env {
+        command.env_clear();
+    }
+    command.envs(config.make_env.iter().map(|(name, value)| (name, value)));
+}
+
 impl Default for MakeWrapper {
     fn default() -> Self {
         Self::new()
@@ -130,6 +336,17 @@ mod tests {
     use std::io::Write;
     use tempfile::tempdir;
 
+    /// Write an executable `fake-make` shell script running `script` into `dir`
+    #[cfg(unix)]
+    fn fake_make(dir: &std::path::Path, script: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let make_path = dir.join("fake-make");
+        std::fs::write(&make_path, format!("#!/bin/sh\n{script}")).unwrap();
+        std::fs::set_permissions(&make_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        make_path
+    }
+
     #[test]
     fn test_make_wrapper_execution() {
         let dir = tempdir().unwrap();
@@ -159,4 +376,176 @@ mod tests {
         let commands = result.unwrap();
         assert_eq!(commands.len(), 1);
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_make_wrapper_parses_stderr() {
+        let dir = tempdir().unwrap();
+        let make_path = fake_make(
+            dir.path(),
+            "echo 'gcc -c out.c -o out.o'\necho 'gcc -c err.c -o err.o' >&2",
+        );
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            no_strict: true,
+            ..Config::default()
+        };
+
+        let wrapper = MakeWrapper::with_make_path(&make_path);
+        let commands = wrapper.execute(&[], &config).unwrap();
+
+        let files: Vec<_> = commands.iter().map(|cmd| cmd.file.as_str()).collect();
+        assert_eq!(files, vec!["out.c", "err.c"]);
+
+        // Streaming produces the same database without buffering it
+        let mut writer = StreamingJsonWriter::new(Vec::new());
+        writer.begin().unwrap();
+        let (buffered, stats) = wrapper
+            .execute_with_writer(&[], &config, Some(&mut writer))
+            .unwrap();
+        writer.finish().unwrap();
+        assert!(buffered.is_empty());
+        assert_eq!(stats.commands_found, 2);
+        assert_eq!(
+            writer.into_inner(),
+            serde_json::to_vec_pretty(&commands).unwrap()
+        );
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test]
+    async fn test_make_wrapper_execute_async() {
+        let dir = tempdir().unwrap();
+        let make_path = fake_make(
+            dir.path(),
+            "echo 'gcc -c out.c -o out.o'\necho 'gcc -c err.c -o err.o' >&2",
+        );
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            no_strict: true,
+            ..Config::default()
+        };
+
+        let wrapper = MakeWrapper::with_make_path(&make_path);
+        let commands = wrapper.execute_async(&[], &config).await.unwrap();
+        assert_eq!(commands, wrapper.execute(&[], &config).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_make_wrapper_custom_flags() {
+        let dir = tempdir().unwrap();
+        let make_path = fake_make(dir.path(), "echo \"gcc $* -c a.c -o a.o\"");
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            no_strict: true,
+            make_flags: vec![String::from("-n"), String::from("-e")],
+            ..Config::default()
+        };
+
+        let wrapper = MakeWrapper::with_make_path(&make_path);
+        let commands = wrapper.execute(&[String::from("all")], &config).unwrap();
+
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap()[..4],
+            ["gcc", "-n", "-e", "all"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_make_env() {
+        let dir = tempdir().unwrap();
+        let make_path = fake_make(
+            dir.path(),
+            "echo \"gcc -DOPT=$OPT -DPKG=$CARGO_PKG_NAME -c a.c -o a.o\"",
+        );
+
+        // Cargo sets CARGO_PKG_NAME for test binaries, so make inherits it
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            no_strict: true,
+            make_env: vec![(String::from("OPT"), String::from("-O0"))],
+            ..Config::default()
+        };
+        let wrapper = MakeWrapper::with_make_path(&make_path);
+        let commands = wrapper.execute(&[], &config).unwrap();
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap()[1..3],
+            ["-DOPT=-O0", "-DPKG=compiledb"]
+        );
+
+        let config = Config {
+            clear_make_env: true,
+            ..config
+        };
+        let commands = wrapper.execute(&[], &config).unwrap();
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap()[1..3],
+            ["-DOPT=-O0", "-DPKG="]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_build_failure_details() {
+        let dir = tempdir().unwrap();
+        let make_path = fake_make(
+            dir.path(),
+            "echo 'a.c:1: warning: unused' >&2
+             echo 'a.c:2: error: expected ;' >&2
+             echo 'make: *** [a.o] Error 1' >&2
+             exit 2",
+        );
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            error_context_lines: 2,
+            ..Config::default()
+        };
+        let wrapper = MakeWrapper::with_make_path(&make_path);
+        let err = wrapper.run_build(&[], &config).unwrap_err();
+
+        assert!(matches!(
+            &err,
+            CompileDbError::BuildFailed { exit_code: Some(2), stderr_tail }
+                if stderr_tail == &["a.c:2: error: expected ;", "make: *** [a.o] Error 1"]
+        ));
+        assert!(err.to_string().starts_with("Build failed with exit code 2"));
+
+        // The dry run reports a failing make the same way
+        let err = wrapper.execute(&[], &config).unwrap_err();
+        assert!(matches!(
+            &err,
+            CompileDbError::BuildFailed { exit_code: Some(2), stderr_tail }
+                if stderr_tail == &["a.c:2: error: expected ;", "make: *** [a.o] Error 1"]
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_make_dry_run_is_single_job() {
+        let dir = tempdir().unwrap();
+        let make_path = fake_make(dir.path(), "echo \"gcc $* -c a.c -o a.o\"");
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            no_strict: true,
+            make_flags: vec![String::from("-n")],
+            ..Config::default()
+        };
+
+        let wrapper = MakeWrapper::with_make_path(&make_path);
+        let args = [String::from("-j8"), String::from("all")];
+        let commands = wrapper.execute(&args, &config).unwrap();
+
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap()[..5],
+            ["gcc", "-n", "-j8", "all", "-j1"]
+        );
+    }
 }
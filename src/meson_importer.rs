@@ -0,0 +1,177 @@
+use crate::{CompileCommand, CompileDbError, expand_tilde};
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tracing::{debug, info};
+
+/// A target from `meson introspect --targets`; only the fields needed to
+/// rebuild compile commands are read
+#[derive(Debug, Deserialize)]
+struct MesonTarget {
+    name: String,
+    #[serde(default)]
+    target_sources: Vec<MesonSources>,
+}
+
+/// A group of sources compiled with the same compiler and parameters.
+/// Newer meson versions also list the linker here, with no `compiler`.
+#[derive(Debug, Deserialize)]
+struct MesonSources {
+    #[serde(default)]
+    compiler: Vec<String>,
+    #[serde(default)]
+    parameters: Vec<String>,
+    #[serde(default)]
+    sources: Vec<String>,
+    #[serde(default)]
+    generated_sources: Vec<String>,
+}
+
+/// Builds compile commands from meson's introspection data.
+///
+/// Meson already writes `compile_commands.json` into its build directory;
+/// this importer is for when only the introspection interface is available
+/// or a subset of targets is wanted. Meson runs every compiler from the
+/// build directory, so it becomes each entry's `directory`.
+pub struct MesonImporter {
+    build_dir: PathBuf,
+}
+
+impl MesonImporter {
+    pub fn new(build_dir: impl AsRef<Path>) -> Self {
+        let build_dir = expand_tilde(build_dir.as_ref());
+        Self {
+            build_dir: std::path::absolute(&build_dir).unwrap_or(build_dir),
+        }
+    }
+
+    /// Run `meson introspect --targets` and import the named targets (all
+    /// targets if `targets` is empty)
+    pub fn run(&self, targets: &[String]) -> Result<Vec<CompileCommand>, CompileDbError> {
+        info!(
+            "Running meson introspect --targets {}",
+            self.build_dir.display()
+        );
+        let output = Command::new("meson")
+            .args(["introspect", "--targets"])
+            .arg(&self.build_dir)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+        if !output.status.success() {
+            return Err(CompileDbError::MakeError(
+                "meson introspect failed".to_string(),
+            ));
+        }
+        self.parse_targets(&String::from_utf8_lossy(&output.stdout), targets)
+    }
+
+    /// Convert the JSON printed by `meson introspect --targets`
+    pub fn parse_targets(
+        &self,
+        json: &str,
+        targets: &[String],
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        let parsed: Vec<MesonTarget> = serde_json::from_str(json)?;
+        let directory = self.build_dir.to_string_lossy();
+
+        let mut commands = Vec::new();
+        for target in parsed {
+            if !targets.is_empty() && !targets.contains(&target.name) {
+                debug!("Skipping meson target {}", target.name);
+                continue;
+            }
+            for group in &target.target_sources {
+                if group.compiler.is_empty() {
+                    continue;
+                }
+                for source in group.sources.iter().chain(&group.generated_sources) {
+                    let mut arguments = group.compiler.clone();
+                    arguments.extend(group.parameters.iter().cloned());
+                    arguments.extend([String::from("-c"), source.clone()]);
+                    commands.push(CompileCommand {
+                        directory: directory.to_string(),
+                        file: source.clone(),
+                        command: None,
+                        arguments: Some(arguments),
+                        output: None,
+                    });
+                }
+            }
+        }
+        info!("Found {} compilation commands", commands.len());
+        Ok(commands)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGETS: &str = r#"[
+        {
+            "name": "app",
+            "id": "app@exe",
+            "type": "executable",
+            "target_sources": [
+                {
+                    "language": "c",
+                    "compiler": ["ccache", "cc"],
+                    "parameters": ["-I../include", "-O2"],
+                    "sources": ["/src/main.c", "/src/util.c"],
+                    "generated_sources": ["/build/version.c"]
+                },
+                {
+                    "linker": ["cc"],
+                    "parameters": ["-lm"]
+                }
+            ]
+        },
+        {
+            "name": "tests",
+            "id": "tests@exe",
+            "type": "executable",
+            "target_sources": [
+                {
+                    "language": "cpp",
+                    "compiler": ["c++"],
+                    "parameters": [],
+                    "sources": ["/src/test.cpp"]
+                }
+            ]
+        }
+    ]"#;
+
+    #[test]
+    fn test_parse_meson_targets() {
+        let importer = MesonImporter::new("/build");
+
+        let commands = importer.parse_targets(TARGETS, &[]).unwrap();
+        let files: Vec<_> = commands.iter().map(|cmd| cmd.file.as_str()).collect();
+        assert_eq!(
+            files,
+            [
+                "/src/main.c",
+                "/src/util.c",
+                "/build/version.c",
+                "/src/test.cpp"
+            ]
+        );
+        assert_eq!(commands[0].directory, "/build");
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap(),
+            &["ccache", "cc", "-I../include", "-O2", "-c", "/src/main.c"]
+        );
+
+        let commands = importer
+            .parse_targets(TARGETS, &[String::from("tests")])
+            .unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            commands[0].arguments.as_ref().unwrap(),
+            &["c++", "-c", "/src/test.cpp"]
+        );
+    }
+}
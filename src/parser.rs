@@ -1,4 +1,8 @@
-use crate::{CompileCommand, CompileDbError, Config};
+use crate::response_file::{ResponseFileQuoting, expand_response_files};
+use crate::{
+    CompileCommand, CompileDbError, Config, Language, dedup_include_args, expand_env_vars,
+    expand_tilde, join_arguments, split_arguments, strip_dependency_args,
+};
 use anyhow::Context;
 use regex::Regex;
 use std::{
@@ -6,23 +10,100 @@ use std::{
     path::{Path, PathBuf},
     process::Command,
 };
-extern crate env_logger;
-extern crate log;
-use log::{debug, info, warn};
+use tracing::{debug, debug_span, info, info_span, warn};
+
+/// Summary statistics collected while parsing a build log
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseStats {
+    /// Number of lines fed to the parser
+    pub lines_processed: usize,
+
+    /// Number of lines matching the compile regex
+    pub lines_matched: usize,
+
+    /// Number of compile commands emitted
+    pub commands_found: usize,
+
+    /// Number of compile commands dropped by exclude patterns
+    pub excluded: usize,
+
+    /// Number of compile commands dropped because the source file was missing
+    pub missing_files: usize,
+
+    /// Number of compile commands dropped by the compiler filter
+    pub compiler_filtered: usize,
+
+    /// Number of compile commands dropped by the language filter
+    pub language_filtered: usize,
+}
+
+impl std::fmt::Display for ParseStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Lines read:                   {}", self.lines_processed)?;
+        writeln!(f, "Lines matching compile regex: {}", self.lines_matched)?;
+        writeln!(f, "Entries accepted:             {}", self.commands_found)?;
+        writeln!(f, "Rejected by exclude:          {}", self.excluded)?;
+        writeln!(f, "Rejected as missing files:    {}", self.missing_files)?;
+        writeln!(
+            f,
+            "Rejected by compiler filter:  {}",
+            self.compiler_filtered
+        )?;
+        write!(
+            f,
+            "Rejected by language filter:  {}",
+            self.language_filtered
+        )
+    }
+}
+
+/// A line that looked like a compile command but could not be turned into
+/// a database entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// 1-based line number in the build log
+    pub line: usize,
+
+    /// The offending line
+    pub content: String,
+
+    /// Why the line was rejected
+    pub reason: String,
+}
+
+impl std::fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.reason, self.content)
+    }
+}
 
 pub struct Parser {
     compile_regex: Regex,
     file_regex: Regex,
+    wrapper_regex: Option<Regex>,
+    libtool_regex: Regex,
     exclude_regex: Option<Regex>,
+    exclude_dir_regexes: Vec<Regex>,
+    include_regexes: Vec<Regex>,
     cd_regex: Regex,
     sh_regex: Regex,
     nested_cmd_regex: Regex,
+    subst_cmd_regex: Regex,
     make_enter_dir: Regex,
     make_leave_dir: Regex,
     make_cmd_dir: Regex,
     checking_make: Regex,
     dir_stack: Vec<PathBuf>,
     working_dir: PathBuf,
+    stats: ParseStats,
+    non_matching_run: usize,
+    last_non_matching: String,
+    last_non_matching_line: usize,
+    warnings: Vec<ParseWarning>,
+    seen_enter_dir: bool,
+    warned_no_enter_dir: bool,
+    seen_make_c: bool,
+    warned_unresolved: bool,
 }
 
 impl Parser {
@@ -31,11 +112,33 @@ impl Parser {
             "Initializing parser with compile regex: {}",
             config.regex_compile
         );
-        info!("File regex: {}", config.regex_file);
+        let regex_file = Self::build_file_regex(config);
+        info!("File regex: {regex_file}");
+        let regex_compile = Self::build_compile_regex(config);
 
-        let compile_regex = Regex::new(&config.regex_compile)
+        let compile_regex = Regex::new(&regex_compile)
             .map_err(|e| CompileDbError::InvalidCommand(e.to_string()))?;
-        let file_regex = Regex::new(&config.regex_file)
+        let file_regex =
+            Regex::new(&regex_file).map_err(|e| CompileDbError::InvalidCommand(e.to_string()))?;
+
+        Self::from_regexes(compile_regex, file_regex, config)
+    }
+
+    /// Create a parser from already compiled compile and file regexes, so a
+    /// long-running caller can compile them once and reuse them. They are used
+    /// as given: `regex_compile`, `regex_file`, `cc`, `cxx` and
+    /// `source_extensions` from `config` are not applied.
+    pub fn from_regexes(
+        compile_regex: Regex,
+        file_regex: Regex,
+        config: &Config,
+    ) -> Result<Self, CompileDbError> {
+        // Anchor the wrapper pattern so it only ever strips a leading prefix
+        let wrapper_regex = config
+            .compiler_wrapper_regex
+            .as_ref()
+            .map(|pattern| Regex::new(&format!(r"^\s*(?:{pattern})(?:\s+|$)")))
+            .transpose()
             .map_err(|e| CompileDbError::InvalidCommand(e.to_string()))?;
 
         // Initialize exclude regex if pattern is provided
@@ -49,9 +152,32 @@ impl Parser {
             None
         };
 
+        if !config.exclude_dir_patterns.is_empty() {
+            info!(
+                "Exclude directory patterns: {:?}",
+                config.exclude_dir_patterns
+            );
+        }
+        let exclude_dir_regexes = config
+            .exclude_dir_patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CompileDbError::InvalidCommand(e.to_string()))?;
+
+        if !config.include_patterns.is_empty() {
+            info!("Include patterns: {:?}", config.include_patterns);
+        }
+        let include_regexes = config
+            .include_patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CompileDbError::InvalidCommand(e.to_string()))?;
+
         // Initialize working directory
         let working_dir = if !config.build_dir.as_os_str().is_empty() {
-            config.build_dir.clone()
+            expand_tilde(&config.build_dir)
         } else {
             std::env::current_dir().map_err(CompileDbError::Io)?
         };
@@ -61,10 +187,18 @@ impl Parser {
         Ok(Self {
             compile_regex,
             file_regex,
+            wrapper_regex,
+            libtool_regex: Regex::new(
+                r"^\s*(?:\S*sh\s+)?\S*libtool\s+(?:--\S+\s+)*?--mode=compile\s+(?:--\S+\s+)*",
+            )
+            .unwrap(),
             exclude_regex,
-            cd_regex: Regex::new(r#"^cd\s+(.*)$"#).unwrap(),
+            exclude_dir_regexes,
+            include_regexes,
+            cd_regex: Regex::new(r#"^(cd|pushd)\s+(.*)$"#).unwrap(),
             sh_regex: Regex::new(r#"\s*(;|&&|\|\|)\s*"#).unwrap(),
             nested_cmd_regex: Regex::new(r#"`([^`]+)`"#).unwrap(),
+            subst_cmd_regex: Regex::new(r#"\$\(([^()]+)\)"#).unwrap(),
             make_enter_dir: Regex::new(
                 r#"^.*?(?:mingw32-make|gmake|make).*?: Entering directory .*['`"](.*)['`"]$"#,
             )
@@ -73,67 +207,304 @@ impl Parser {
                 r#"^.*?(?:mingw32-make|gmake|make).*?: Leaving directory .*'(.*)'$"#,
             )
             .unwrap(),
-            make_cmd_dir: Regex::new(r#"^\s*(?:mingw32-make|gmake|make).*?-C\s+(.*?)(\s|$)"#)
-                .unwrap(),
+            make_cmd_dir: Regex::new(
+                r#"^\s*(?:mingw32-make|gmake|make).*?-C\s+(?:"([^"]*)"|'([^']*)'|(\S+))"#,
+            )
+            .unwrap(),
             checking_make: Regex::new(r#"^\s?checking whether .*(yes|no)$"#).unwrap(),
             dir_stack: vec![working_dir.clone()],
             working_dir,
+            stats: ParseStats::default(),
+            non_matching_run: 0,
+            last_non_matching: String::new(),
+            last_non_matching_line: 0,
+            warnings: Vec::new(),
+            seen_enter_dir: false,
+            warned_no_enter_dir: false,
+            seen_make_c: false,
+            warned_unresolved: false,
         })
     }
 
-    /// Parse a single line of build output
-    pub fn parse_line(&mut self, line: &str, config: &Config) -> Vec<CompileCommand> {
+    /// Assemble the compile regex, also matching the `cc`/`cxx` compilers
+    fn build_compile_regex(config: &Config) -> String {
+        // Only the binary matters, e.g. CC="ccache /opt/bin/mycc" gives "mycc"
+        let compilers: Vec<String> = [&config.cc, &config.cxx]
+            .into_iter()
+            .flatten()
+            .filter_map(|compiler| compiler.split_whitespace().last())
+            .filter_map(|compiler| Path::new(compiler).file_name())
+            .map(|name| regex::escape(&name.to_string_lossy()))
+            .collect();
+
+        if compilers.is_empty() {
+            return config.regex_compile.clone();
+        }
+
+        info!("Also matching compilers from CC/CXX: {compilers:?}");
+        format!(
+            r"(?:{})|(?:[^/]*/)*(?:{})(?:\s|$)",
+            config.regex_compile,
+            compilers.join("|")
+        )
+    }
+
+    /// Assemble the source file regex, adding any extra extensions from the config
+    fn build_file_regex(config: &Config) -> String {
+        let extensions: Vec<String> = config
+            .source_extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.'))
+            .filter(|ext| !ext.is_empty())
+            .map(regex::escape)
+            .collect();
+
+        if extensions.is_empty() {
+            return config.regex_file.clone();
+        }
+
+        format!(
+            r"(?:{})|\s-c\s+(\S+\.(?:{}))\s+-o\s",
+            config.regex_file,
+            extensions.join("|")
+        )
+    }
+
+    /// The directory compile commands are currently resolved against
+    pub fn working_dir(&self) -> &Path {
+        &self.working_dir
+    }
+
+    /// Set the directory that the following lines are resolved against,
+    /// forgetting any directories entered via make's `Entering directory`.
+    ///
+    /// ```
+    /// use compiledb::Config;
+    /// use compiledb::parser::Parser;
+    /// use std::path::{Path, PathBuf};
+    ///
+    /// let config = Config {
+    ///     no_strict: true,
+    ///     ..Config::default()
+    /// };
+    /// let mut parser = Parser::new(&config).unwrap();
+    /// parser.set_working_dir(PathBuf::from("/src/project"));
+    /// assert_eq!(parser.working_dir(), Path::new("/src/project"));
+    ///
+    /// let commands = parser.parse_line("gcc -c main.c -o main.o", &config).unwrap();
+    /// assert_eq!(commands[0].directory, "/src/project");
+    /// assert_eq!(commands[0].file, "main.c");
+    /// ```
+    pub fn set_working_dir(&mut self, dir: PathBuf) {
+        self.dir_stack = vec![dir.clone()];
+        self.working_dir = dir;
+    }
+
+    /// Statistics accumulated over all lines parsed so far
+    pub fn stats(&self) -> &ParseStats {
+        &self.stats
+    }
+
+    /// Lines that matched the compile regex but were rejected, collected by
+    /// [`Parser::try_parse_line`] and the file/reader entry points
+    pub fn warnings(&self) -> &[ParseWarning] {
+        &self.warnings
+    }
+
+    /// Parse a single line of build output. Fails with
+    /// [`CompileDbError::ParseFailure`] when the line looks like a compile
+    /// command but no source file or compiler could be extracted from it.
+    pub fn parse_line(
+        &mut self,
+        line: &str,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        Ok(self
+            .parse_line_detailed(line, config)?
+            .into_iter()
+            .map(|(cmd, _)| cmd)
+            .collect())
+    }
+
+    /// Parse a single line of build output, also returning the basename of the
+    /// compiler that matched each command (e.g. `g++`)
+    pub fn parse_line_detailed(
+        &mut self,
+        line: &str,
+        config: &Config,
+    ) -> Result<Vec<(CompileCommand, String)>, CompileDbError> {
+        self.stats.lines_processed += 1;
         let line = line.trim();
         let mut commands = Vec::new();
 
-        // Skip empty lines and make checking lines
-        if line.is_empty() || self.checking_make.is_match(line) {
-            return commands;
+        if config.warn_no_enter_dir
+            && !self.seen_enter_dir
+            && !self.seen_make_c
+            && !self.warned_no_enter_dir
+            && self.stats.lines_processed >= NO_ENTER_DIR_WARN_LINES
+        {
+            warn!(
+                "No 'Entering directory' lines in the first {NO_ENTER_DIR_WARN_LINES} lines; \
+                 the log may lack make's -w output, so directories cannot be tracked. \
+                 Use --build-dir to set the directory commands ran in."
+            );
+            self.warned_no_enter_dir = true;
+        }
+
+        // Skip empty lines and MSVC /showIncludes notes
+        if line.is_empty() || (config.filter_msvc_includes && line.starts_with(MSVC_INCLUDE_NOTE)) {
+            return Ok(commands);
+        }
+
+        // Skip make checking lines
+        if self.checking_make.is_match(line) {
+            self.record_non_matching(line);
+            return Ok(commands);
         }
 
         // Handle directory changes
         if self.update_working_dir(line) {
-            return commands;
+            return Ok(commands);
+        }
+
+        // A standalone cd line persists for the lines that follow it
+        if let Some(caps) = self.cd_regex.captures(line) {
+            if self.split_commands(line).len() == 1 {
+                self.change_dir(&caps[2]);
+                return Ok(commands);
+            }
         }
 
         // Skip non-compilation commands
         if !self.compile_regex.is_match(line) {
             debug!("Line did not match compile regex: {line}");
-            return commands;
+            self.record_non_matching(line);
+            return Ok(commands);
         }
         debug!("Found potential compile command: {line}");
+        self.stats.lines_matched += 1;
+        self.non_matching_run = 0;
+
+        // Substitute make variables left in printed recipes, before they can
+        // be taken for $(...) command substitutions
+        let line = if config.expand_make_vars {
+            expand_make_vars(line, &self.working_dir)
+        } else {
+            line.to_string()
+        };
 
-        // Process nested commands (backticks)
-        let line = self.process_nested_commands(line);
+        // Process nested commands (backticks and $(...))
+        let line = self.process_nested_commands(&line);
 
         // Replace escaped quotes
         let line = line.replace(r#"\""#, r#"""#);
 
+        // Directory changes inside a compound line only apply to that line,
+        // just like the subshell make runs each recipe line in
+        let line_dir = self.working_dir.clone();
+        let mut pushd_stack = Vec::new();
+
         // Split into individual commands
         for cmd in self.split_commands(&line) {
-            // Handle cd commands
+            // Handle cd/pushd commands
             if let Some(caps) = self.cd_regex.captures(&cmd) {
-                if let Some(dir) = caps.get(1) {
-                    let new_dir = PathBuf::from(dir.as_str());
-                    self.working_dir = if new_dir.is_absolute() {
-                        new_dir
-                    } else {
-                        self.working_dir.join(new_dir)
-                    };
-                    info!("Changed directory to: {}", self.working_dir.display());
+                if caps[1].starts_with("pushd") {
+                    pushd_stack.push(self.working_dir.clone());
+                }
+                self.change_dir(&caps[2]);
+                continue;
+            }
+            if cmd == "popd" {
+                if let Some(dir) = pushd_stack.pop() {
+                    self.working_dir = dir;
                 }
                 continue;
             }
 
-            // Process compilation command
-            if self.compile_regex.is_match(&cmd) {
-                if let Some(compile_cmd) = self.process_compile_command(&cmd, config) {
-                    commands.push(compile_cmd);
+            // Only fragments naming both a compiler and a source file are
+            // compile commands; `export CC=gcc` and the like are skipped
+            if self.compile_regex.is_match(&cmd) && self.file_regex.is_match(&cmd) {
+                match self.process_compile_command(&cmd, config) {
+                    Ok(Some(detailed)) => {
+                        self.stats.commands_found += 1;
+                        commands.push(detailed);
+                    }
+                    Ok(None) => {}
+                    Err(reason) => {
+                        self.working_dir = line_dir;
+                        return Err(CompileDbError::ParseFailure {
+                            line: self.stats.lines_processed,
+                            content: cmd,
+                            reason,
+                        });
+                    }
                 }
             }
         }
+        self.working_dir = line_dir;
+
+        Ok(commands)
+    }
+
+    /// Change the working directory, resolving `dir` against the current one
+    fn change_dir(&mut self, dir: &str) {
+        let new_dir = expand_tilde(Path::new(dir));
+        self.working_dir = if new_dir.is_absolute() {
+            new_dir
+        } else {
+            self.working_dir.join(new_dir)
+        };
+        info!("Changed directory to: {}", self.working_dir.display());
+    }
+
+    /// Parse a single line, recording per-line failures as warnings instead of
+    /// returning them. Fails once `max_consecutive_non_matching` lines in a
+    /// row did not look like compile commands.
+    pub fn try_parse_line(
+        &mut self,
+        line: &str,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        let commands = match self.parse_line(line, config) {
+            Ok(commands) => commands,
+            Err(CompileDbError::ParseFailure {
+                line,
+                content,
+                reason,
+            }) => {
+                let warning = ParseWarning {
+                    line,
+                    content,
+                    reason,
+                };
+                warn!("Skipping {warning}");
+                self.warnings.push(warning);
+                Vec::new()
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some(max) = config.max_consecutive_non_matching {
+            if max > 0 && self.non_matching_run >= max {
+                return Err(CompileDbError::ParseFailure {
+                    line: self.last_non_matching_line,
+                    content: self.last_non_matching.clone(),
+                    reason: format!(
+                        "{} consecutive lines did not match the compile regex",
+                        self.non_matching_run
+                    ),
+                });
+            }
+        }
 
-        commands
+        Ok(commands)
+    }
+
+    /// Track a line that is neither a compile command nor a directory change
+    fn record_non_matching(&mut self, line: &str) {
+        self.non_matching_run += 1;
+        self.last_non_matching = line.to_string();
+        self.last_non_matching_line = self.stats.lines_processed;
     }
 
     /// Parse build log file and extract compilation commands
@@ -142,31 +513,156 @@ impl Parser {
         path: &Path,
         config: &Config,
     ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        self.parse_file_with_stats(path, config)
+            .map(|(commands, _)| commands)
+    }
+
+    /// Parse build log file, also returning the accumulated parse statistics
+    pub fn parse_file_with_stats(
+        &mut self,
+        path: &Path,
+        config: &Config,
+    ) -> Result<(Vec<CompileCommand>, ParseStats), CompileDbError> {
+        let _span = info_span!("parse_file", path = ?path).entered();
         info!("Parsing build log file: {}", path.display());
 
         let file = std::fs::File::open(path)
             .with_context(|| format!("Failed to open build log file: {}", path.display()))
             .map_err(|e| CompileDbError::Io(std::io::Error::other(e)))?;
 
-        let reader = BufReader::new(file);
+        let commands = self.parse_reader(BufReader::new(file), config)?;
+        Ok((commands, self.stats.clone()))
+    }
+
+    /// Parse build output from any buffered reader, such as stdin or a pipe
+    pub fn parse_reader<R: BufRead>(
+        &mut self,
+        reader: R,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
         let mut commands = Vec::new();
-        let mut cmd_count = 0;
+        self.for_each_command(reader, config, |cmd| {
+            debug!("Adding command {}: {:?}", commands.len(), cmd);
+            commands.push(cmd);
+            Ok(())
+        })?;
+        Ok(commands)
+    }
+
+    /// Parse build output that is already split into lines, such as a
+    /// `Vec<String>` or the output of [`str::lines`]
+    pub fn parse_lines<I, S>(
+        &mut self,
+        lines: I,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut commands = Vec::new();
+        for line in lines {
+            commands.extend(self.try_parse_line(line.as_ref(), config)?);
+        }
+        Ok(commands)
+    }
+
+    /// Async counterpart of [`parse_reader`](Self::parse_reader), reading
+    /// lines without blocking the executor
+    #[cfg(feature = "tokio")]
+    pub async fn parse_reader_async<R>(
+        &mut self,
+        reader: R,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError>
+    where
+        R: tokio::io::AsyncBufRead + Unpin,
+    {
+        use tokio::io::AsyncBufReadExt;
+
+        let mut commands = Vec::new();
+        let mut segments = reader.split(b'\n');
+        let mut line_count = 0;
+        while let Some(bytes) = segments.next_segment().await? {
+            line_count += 1;
+            let line = decode_line(bytes, line_count, config.keep_going)?;
+            commands.extend(self.try_parse_line(&line, config)?);
+        }
+        Ok(commands)
+    }
+
+    /// Parse build output from any buffered reader without stopping at bad
+    /// lines. Returns the commands found along with every per-line error
+    /// (undecodable lines, lines that look like compile commands but cannot
+    /// be parsed) and its 1-based line number. A failed read ends the parse
+    /// and is reported as the last error.
+    pub fn parse_reader_collecting<R: BufRead>(
+        &mut self,
+        reader: R,
+        config: &Config,
+    ) -> (Vec<CompileCommand>, Vec<(usize, CompileDbError)>) {
+        let mut commands = Vec::new();
+        let mut errors = Vec::new();
+
+        for (index, line) in reader.split(b'\n').enumerate() {
+            let number = index + 1;
+            let bytes = match line {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    errors.push((number, CompileDbError::Io(e)));
+                    break;
+                }
+            };
+            let line = match decode_line(bytes, number, config.keep_going) {
+                Ok(line) => line,
+                Err(e) => {
+                    // Keep the parser's own line numbers in step with the reader
+                    self.stats.lines_processed += 1;
+                    errors.push((number, e));
+                    continue;
+                }
+            };
+            match self.parse_line(&line, config) {
+                Ok(found) => commands.extend(found),
+                Err(e) => errors.push((number, e)),
+            }
+        }
+
+        (commands, errors)
+    }
+
+    /// Parse build output from `reader`, handing each compile command to `f`
+    /// as soon as it is found instead of collecting them
+    pub fn for_each_command<R, F>(
+        &mut self,
+        reader: R,
+        config: &Config,
+        mut f: F,
+    ) -> Result<(), CompileDbError>
+    where
+        R: BufRead,
+        F: FnMut(CompileCommand) -> Result<(), CompileDbError>,
+    {
         let mut line_count = 0;
+        let mut cmd_count = 0;
 
-        for line in reader.lines() {
+        for line in reader.split(b'\n') {
             line_count += 1;
-            let line = line.map_err(CompileDbError::Io)?;
-            let new_commands = self.parse_line(&line, config);
-            for cmd in new_commands {
-                debug!("Adding command {}: {:?}", cmd_count, cmd);
-                commands.push(cmd);
+            let line = decode_line(line?, line_count, config.keep_going)?;
+            let _line_span = debug_span!("line", number = line_count).entered();
+            for cmd in self.try_parse_line(&line, config)? {
                 cmd_count += 1;
+                f(cmd)?;
+            }
+
+            if config.progress && line_count % PROGRESS_INTERVAL == 0 {
+                eprintln!("Processed {line_count} lines, found {cmd_count} compile commands");
             }
         }
 
         info!("Processed {} lines from build log", line_count);
-        info!("Found {} compilation commands", commands.len());
-        Ok(commands)
+        info!("Found {} compilation commands", cmd_count);
+        Ok(())
     }
 
     /// Split a command string into individual commands based on shell operators
@@ -179,27 +675,31 @@ impl Parser {
             .collect()
     }
 
-    /// Process nested commands (backtick substitution)
+    /// Process nested commands (backtick and `$(...)` substitution), e.g.
+    /// `$(pkg-config --cflags gtk+-3.0)` left in the log by the Makefile
+    ///
+    /// `$(NAME)` is an unexpanded make variable rather than a command and is
+    /// left alone, as is any substitution whose command fails.
     fn process_nested_commands(&self, line: &str) -> String {
         let mut result = line.to_string();
-        while let Some(caps) = self.nested_cmd_regex.captures(&result) {
-            if let Some(nested_cmd) = caps.get(1) {
-                let output = Command::new("sh")
-                    .arg("-c")
-                    .arg(nested_cmd.as_str())
-                    .output();
-
-                match output {
-                    Ok(output) if output.status.success() => {
-                        let cmd_output = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                        result = result.replace(&caps[0], &cmd_output);
+        for regex in [&self.nested_cmd_regex, &self.subst_cmd_regex] {
+            result = regex
+                .replace_all(&result, |caps: &regex::Captures| {
+                    let nested_cmd = &caps[1];
+                    if caps[0].starts_with('$') && is_make_variable_name(nested_cmd) {
+                        return caps[0].to_string();
                     }
-                    _ => {
-                        warn!("Failed to execute nested command: {}", nested_cmd.as_str());
-                        break;
+                    match Command::new("sh").arg("-c").arg(nested_cmd).output() {
+                        Ok(output) if output.status.success() => {
+                            String::from_utf8_lossy(&output.stdout).trim().to_string()
+                        }
+                        _ => {
+                            warn!("Failed to execute nested command: {nested_cmd}");
+                            caps[0].to_string()
+                        }
                     }
-                }
-            }
+                })
+                .into_owned();
         }
         result
     }
@@ -211,10 +711,12 @@ impl Parser {
                 let enter_dir = PathBuf::from(dir.as_str());
                 self.dir_stack.insert(0, enter_dir.clone());
                 self.working_dir = enter_dir;
+                self.seen_enter_dir = true;
                 info!("Entering directory: {}", self.working_dir.display());
                 return true;
             }
         } else if self.make_leave_dir.captures(line).is_some() {
+            self.seen_enter_dir = true;
             if !self.dir_stack.is_empty() {
                 self.dir_stack.remove(0);
                 if !self.dir_stack.is_empty() {
@@ -224,9 +726,25 @@ impl Parser {
                 return true;
             }
         } else if let Some(caps) = self.make_cmd_dir.captures(line) {
-            if let Some(dir) = caps.get(1) {
+            // The directory may be double-quoted, single-quoted or bare
+            if let Some(dir) = caps.get(1).or(caps.get(2)).or(caps.get(3)) {
                 let enter_dir = PathBuf::from(dir.as_str());
-                if enter_dir.as_os_str() != "." {
+                if !self.seen_enter_dir {
+                    // With --no-print-directory there are no Enter/Leave lines
+                    // to say when a sub-make ends, so the -C lines are all we
+                    // have. Nested -C paths exist below the current directory;
+                    // anything else is taken as a sibling under the root. This
+                    // probes the filesystem, so it needs the source tree.
+                    let root = self.dir_stack.last().cloned().unwrap_or_default();
+                    let nested = self.working_dir.join(&enter_dir);
+                    self.working_dir = if nested.is_dir() {
+                        nested
+                    } else {
+                        root.join(&enter_dir)
+                    };
+                    self.seen_make_c = true;
+                    info!("Make -C directory: {}", self.working_dir.display());
+                } else if enter_dir.as_os_str() != "." {
                     self.dir_stack.insert(0, enter_dir.clone());
                     self.working_dir = enter_dir;
                     info!("Make -C directory: {}", self.working_dir.display());
@@ -237,32 +755,113 @@ impl Parser {
         false
     }
 
-    /// Process a compilation command
-    fn process_compile_command(&self, command: &str, config: &Config) -> Option<CompileCommand> {
-        // Split command into arguments
-        let args: Vec<String> = command.split_whitespace().map(String::from).collect();
+    /// Resolve the compiler token to a full path. Tokens containing a path
+    /// separator are resolved against the working directory; plain names go
+    /// through a PATH lookup.
+    fn resolve_compiler(&self, compiler: &str) -> Option<PathBuf> {
+        let path = Path::new(compiler);
+        if path.components().count() > 1 || path.is_absolute() {
+            if let Ok(full_path) = self.working_dir.join(path).canonicalize() {
+                return Some(full_path);
+            }
+        }
+        which::which(compiler).ok()
+    }
+
+    /// Process a compilation command, returning it with the compiler basename.
+    /// `Ok(None)` means the command was skipped on purpose; `Err` carries the
+    /// reason it could not be parsed.
+    fn process_compile_command(
+        &mut self,
+        command: &str,
+        config: &Config,
+    ) -> Result<Option<(CompileCommand, String)>, String> {
+        // Strip `libtool --mode=compile`; the compiler command follows it
+        let command = match self.libtool_regex.find(command) {
+            Some(prefix) => {
+                debug!("Stripped libtool wrapper: {}", prefix.as_str().trim());
+                &command[prefix.end()..]
+            }
+            None => command,
+        };
+
+        // Strip a site-specific launcher prefix before looking for the compiler
+        let command = match self.wrapper_regex.as_ref().and_then(|re| re.find(command)) {
+            Some(prefix) => {
+                debug!("Stripped compiler wrapper: {}", prefix.as_str().trim());
+                &command[prefix.end()..]
+            }
+            None => command,
+        };
+
+        // Split command into arguments, keeping quoted arguments intact
+        let args = split_arguments(command);
 
-        // Find compiler command
+        // Find compiler command, skipping env assignments and compiler wrappers
         let compile_idx = args
             .iter()
-            .position(|arg| self.compile_regex.is_match(arg))?;
-        let arguments = args[compile_idx..].to_vec();
+            .position(|arg| {
+                !is_env_assignment(arg)
+                    && !is_compiler_wrapper(arg)
+                    && self.compile_regex.is_match(arg)
+            })
+            .ok_or_else(|| String::from("no argument matched the compile regex"))?;
+        let mut arguments = args[compile_idx..].to_vec();
+
+        // A linker matched by a broad custom compile regex never compiles
+        let compiles = has_compile_flag(&arguments);
+        if config.filter_linker_commands && !compiles && is_linker(&arguments[0]) {
+            debug!("No -c flag, skipping linker command: {command}");
+            return Ok(None);
+        }
 
         // Extract source file
-        let file_match = self.file_regex.captures(command)?;
-        let file = file_match.get(1)?.as_str().to_string();
+        let Some(file_match) = self.file_regex.captures(command) else {
+            return Ok(None);
+        };
+        // The first participating group is the file, whichever alternative matched
+        let file = file_match
+            .iter()
+            .skip(1)
+            .flatten()
+            .next()
+            .ok_or_else(|| String::from("file regex matched but captured no source file"))?
+            .as_str()
+            .to_string();
         debug!("Found source file: {file}");
 
+        // Without -c the source is compiled and linked in one step; record
+        // the compile on its own, as tools expect
+        if !compiles {
+            // Insert the flag before the source, which sits at `index + 1`
+            if let Some(index) = arguments.iter().skip(1).position(|arg| *arg == file) {
+                let flag = match ResponseFileQuoting::for_compiler(&arguments[0]) {
+                    ResponseFileQuoting::Msvc => "/c",
+                    ResponseFileQuoting::Gnu => "-c",
+                };
+                debug!("No -c flag, inferring it for {file}");
+                arguments.insert(index + 1, String::from(flag));
+            }
+        }
+
+        // Substitute $VAR and ${VAR} references left in the log by the build system
+        let (file, working_dir) = if config.expand_env_vars {
+            let working_dir = expand_env_vars(&self.working_dir.to_string_lossy());
+            (expand_env_vars(&file), PathBuf::from(working_dir))
+        } else {
+            (file, self.working_dir.clone())
+        };
+
         // Convert absolute path to relative path if needed
         let file = if Path::new(&file).is_absolute() {
             let file_path = PathBuf::from(&file);
             // Try to strip the working directory prefix
-            if let Ok(rel_path) = file_path.strip_prefix(&self.working_dir) {
+            if let Ok(rel_path) = file_path.strip_prefix(&working_dir) {
                 rel_path.to_string_lossy().into_owned()
             } else {
                 // If the file path doesn't start with working_dir, try to find the common suffix
                 let file_components: Vec<_> = file_path.components().collect();
-                let working_dir_components: Vec<_> = self.working_dir.components().collect();
+                let working_dir_components: Vec<_> = working_dir.components().collect();
 
                 // Find where the paths start to match
                 let mut match_start = None;
@@ -293,7 +892,7 @@ impl Parser {
         // Get full path for compiler if requested
         let mut final_args = if config.full_path {
             let mut args = arguments.clone();
-            if let Ok(full_path) = which::which(&args[0]) {
+            if let Some(full_path) = self.resolve_compiler(&args[0]) {
                 args[0] = full_path.to_string_lossy().into_owned();
             }
             args
@@ -306,7 +905,7 @@ impl Parser {
             if c_idx + 1 < final_args.len() {
                 let arg_file = &final_args[c_idx + 1];
                 if Path::new(arg_file).is_absolute() {
-                    if let Ok(rel_path) = PathBuf::from(arg_file).strip_prefix(&self.working_dir) {
+                    if let Ok(rel_path) = PathBuf::from(arg_file).strip_prefix(&working_dir) {
                         final_args[c_idx + 1] = rel_path.to_string_lossy().into_owned();
                     }
                 }
@@ -317,34 +916,136 @@ impl Parser {
         if let Some(ref exclude_re) = self.exclude_regex {
             if exclude_re.is_match(&file) {
                 info!("File {file} excluded");
-                return None;
+                self.stats.excluded += 1;
+                return Ok(None);
+            }
+        }
+
+        // With include patterns, only matching files are kept
+        if !self.include_regexes.is_empty()
+            && !self.include_regexes.iter().any(|re| re.is_match(&file))
+        {
+            info!("File {file} not included");
+            self.stats.excluded += 1;
+            return Ok(None);
+        }
+
+        let directory = working_dir.to_string_lossy();
+        if self
+            .exclude_dir_regexes
+            .iter()
+            .any(|re| re.is_match(&directory))
+        {
+            info!("Directory {directory} excluded");
+            self.stats.excluded += 1;
+            return Ok(None);
+        }
+
+        // Check compiler filter against the compiler binary basename
+        let compiler = Path::new(&args[compile_idx])
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if !config.compiler_filter.is_empty() && !config.compiler_filter.contains(&compiler) {
+            info!("Compiler {compiler} not in filter, skipping {file}");
+            self.stats.compiler_filtered += 1;
+            return Ok(None);
+        }
+
+        // Check language filter against the -x flag or file extension
+        if !config.languages.is_empty() {
+            let language = Language::detect(&file, &args[compile_idx..]);
+            if !language.is_some_and(|language| config.languages.contains(&language)) {
+                info!("Language {language:?} not in filter, skipping {file}");
+                self.stats.language_filtered += 1;
+                return Ok(None);
             }
         }
 
         // Check file existence in strict mode
         if !config.no_strict {
-            let file_path = self.working_dir.join(&file);
+            let file_path = working_dir.join(&file);
             if !file_path.exists() {
                 warn!("Source file not found: {}", file_path.display());
-                return None;
+                if Path::new(&file).is_relative()
+                    && !self.seen_enter_dir
+                    && !self.seen_make_c
+                    && !self.warned_unresolved
+                {
+                    warn!(
+                        "Relative source files cannot be resolved and the log has no \
+                         'Entering directory' or 'make -C' lines; use --build-dir or \
+                         make's -w to provide the directory"
+                    );
+                    self.warned_unresolved = true;
+                }
+                if !config.warn_missing {
+                    self.stats.missing_files += 1;
+                    return Ok(None);
+                }
+                // Keep the entry, but leave a record of the problem
+                self.warnings.push(ParseWarning {
+                    line: self.stats.lines_processed,
+                    content: command.to_string(),
+                    reason: format!("source file not found: {}", file_path.display()),
+                });
+            }
+
+            // A missing generated header breaks the entry just like a missing source
+            if config.strict_includes {
+                if let Some(header) = missing_forced_include(&final_args, &working_dir) {
+                    warn!("Forced include not found: {header}");
+                    if !config.warn_missing {
+                        self.stats.missing_files += 1;
+                        return Ok(None);
+                    }
+                    self.warnings.push(ParseWarning {
+                        line: self.stats.lines_processed,
+                        content: command.to_string(),
+                        reason: format!("forced include not found: {header}"),
+                    });
+                }
             }
         }
 
+        // Inline @file response files so tools see the real flags
+        if config.expand_response_files {
+            final_args = expand_response_files(final_args, &working_dir);
+        }
+
+        // Drop dependency-generation flags, which only make sense for the real build
+        if config.strip_deps {
+            final_args = strip_dependency_args(final_args.iter().map(String::as_str));
+        }
+
+        // Drop repeated include directories, keeping the first of each
+        if config.dedup_includes {
+            final_args = dedup_include_args(final_args);
+        }
+
+        // Rewrite path prefixes so the database works in other checkouts
+        let file = if config.rewrite_paths.is_empty() {
+            file
+        } else {
+            rewrite_path_args(&mut final_args, &config.rewrite_paths);
+            rewrite_path(&file, &config.rewrite_paths).unwrap_or(file)
+        };
+
         // Add custom macros if specified
         final_args.extend(config.macros.iter().cloned());
 
         info!(
             "Found compile command for file: {} in directory: {}",
             file,
-            self.working_dir.display()
+            working_dir.display()
         );
         debug!("Command arguments: {:?}", final_args);
 
-        Some(CompileCommand {
-            directory: self.working_dir.to_string_lossy().into_owned(),
+        let compile_cmd = CompileCommand {
+            directory: working_dir.to_string_lossy().into_owned(),
             file,
             command: if config.command_style {
-                Some(final_args.join(" "))
+                Some(join_arguments(&final_args))
             } else {
                 None
             },
@@ -354,7 +1055,181 @@ impl Parser {
                 Some(final_args)
             },
             output: None,
-        })
+        };
+        Ok(Some((compile_cmd, compiler)))
+    }
+}
+
+/// Flags whose value is a path, either as the next argument or glued on
+const PATH_FLAGS: &[&str] = &["-I", "-isystem", "-iquote", "-include", "-o", "-c"];
+
+/// Whether a command only compiles: it has `-c` (or flang's `-fc`), or `/c`
+/// for MSVC-style drivers
+fn has_compile_flag(arguments: &[String]) -> bool {
+    let msvc = arguments.first().is_some_and(|compiler| {
+        ResponseFileQuoting::for_compiler(compiler) == ResponseFileQuoting::Msvc
+    });
+    arguments
+        .iter()
+        .skip(1)
+        .any(|arg| arg == "-c" || arg == "-fc" || (msvc && arg.eq_ignore_ascii_case("/c")))
+}
+
+/// Linker drivers, which never compile, also with a target prefix
+/// (`arm-none-eabi-ld`) or `.exe` suffix
+const LINKERS: &[&str] = &[
+    "ld", "lld", "ld.lld", "ld.gold", "ld.bfd", "gold", "mold", "link",
+];
+
+fn is_linker(program: &str) -> bool {
+    let name = program
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let name = name.trim_end_matches(".exe");
+    LINKERS
+        .iter()
+        .any(|linker| name == *linker || name.ends_with(&format!("-{linker}")))
+}
+
+/// Whether the body of a `$(...)` is a bare name such as `CFLAGS`, i.e. a
+/// make variable the log left unexpanded
+fn is_make_variable_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replace `$(CURDIR)` and `${CURDIR}` with make's directory, `working_dir`
+fn expand_make_vars(line: &str, working_dir: &Path) -> String {
+    static CURDIR_REGEX: std::sync::LazyLock<Regex> =
+        std::sync::LazyLock::new(|| Regex::new(r"\$(?:\(CURDIR\)|\{CURDIR\})").unwrap());
+
+    let curdir = working_dir.to_string_lossy();
+    CURDIR_REGEX
+        .replace_all(line, regex::NoExpand(&curdir))
+        .into_owned()
+}
+
+/// Apply the first `(from, to)` rule whose prefix matches `path`
+fn rewrite_path(path: &str, rules: &[(String, String)]) -> Option<String> {
+    rules.iter().find_map(|(from, to)| {
+        path.strip_prefix(from.as_str())
+            .map(|rest| format!("{to}{rest}"))
+    })
+}
+
+/// Rewrite the path values of include, source and output arguments in place
+fn rewrite_path_args(args: &mut [String], rules: &[(String, String)]) {
+    let mut value_follows = false;
+    for arg in args.iter_mut() {
+        if std::mem::take(&mut value_follows) {
+            if let Some(rewritten) = rewrite_path(arg, rules) {
+                *arg = rewritten;
+            }
+            continue;
+        }
+        if PATH_FLAGS.contains(&arg.as_str()) {
+            value_follows = true;
+            continue;
+        }
+        // Glued forms such as -I/abs/include; -c never takes a glued value
+        let glued = PATH_FLAGS
+            .iter()
+            .filter(|flag| **flag != "-c")
+            .find_map(|flag| Some((*flag, arg.strip_prefix(flag)?)));
+        if let Some((flag, value)) = glued {
+            if let Some(rewritten) = rewrite_path(value, rules) {
+                *arg = format!("{flag}{rewritten}");
+            }
+        }
+    }
+}
+
+/// The first `-include`/`-imacros` file in `args` that cannot be found. Like
+/// the compiler, look in `working_dir` first, then in the `-iquote`, `-I`
+/// and `-isystem` directories.
+fn missing_forced_include<'a>(args: &'a [String], working_dir: &Path) -> Option<&'a str> {
+    let mut forced = Vec::new();
+    let mut search_dirs = vec![working_dir.to_path_buf()];
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        if arg == "-include" || arg == "-imacros" {
+            forced.extend(args_iter.next().map(String::as_str));
+            continue;
+        }
+        let Some(flag) = ["-iquote", "-isystem", "-I"]
+            .into_iter()
+            .find(|flag| arg.starts_with(flag))
+        else {
+            continue;
+        };
+        let dir = if arg == flag {
+            args_iter.next().map(String::as_str)
+        } else {
+            Some(&arg[flag.len()..])
+        };
+        search_dirs.extend(dir.map(|dir| working_dir.join(dir)));
+    }
+
+    forced
+        .into_iter()
+        .find(|header| !search_dirs.iter().any(|dir| dir.join(header).exists()))
+}
+
+/// Decode a raw line of build output. Invalid UTF-8 is an error unless
+/// `keep_going` is set, in which case the offending bytes are replaced.
+pub(crate) fn decode_line(
+    mut bytes: Vec<u8>,
+    number: usize,
+    keep_going: bool,
+) -> Result<String, CompileDbError> {
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(line) => Ok(line),
+        Err(e) if keep_going => {
+            warn!("Line {number} is not valid UTF-8, replacing invalid bytes");
+            Ok(String::from_utf8_lossy(e.as_bytes()).into_owned())
+        }
+        Err(_) => Err(CompileDbError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("line {number} is not valid UTF-8"),
+        ))),
+    }
+}
+
+/// Prefix of the header dependency lines printed by `cl.exe /showIncludes`
+const MSVC_INCLUDE_NOTE: &str = "Note: including file:";
+
+/// Number of lines between progress reports
+const PROGRESS_INTERVAL: usize = 10_000;
+
+/// Lines after which a log without `Entering directory` lines is reported
+const NO_ENTER_DIR_WARN_LINES: usize = 1000;
+
+/// Launchers that wrap the real compiler, e.g. `icecc gcc -c foo.c`
+const COMPILER_WRAPPERS: &[&str] = &["ccache", "sccache", "distcc", "icecc"];
+
+fn is_compiler_wrapper(arg: &str) -> bool {
+    Path::new(arg)
+        .file_name()
+        .is_some_and(|name| COMPILER_WRAPPERS.iter().any(|wrapper| name == *wrapper))
+}
+
+/// Whether `arg` is a shell variable assignment such as `ICECC_VERSION=...`
+pub(crate) fn is_env_assignment(arg: &str) -> bool {
+    match arg.split_once('=') {
+        Some((name, _)) => {
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
     }
 }
 
@@ -365,6 +1240,10 @@ mod tests {
     use std::io::Write;
     use tempfile::tempdir;
 
+    /// A recipe line from a real build with a printf prefix, a subshell and
+    /// duplicated include directories
+    const COMPLEX_COMMAND: &str = r#"/usr/bin/printf " [ %-17.17s ]  CC           drivers/module/core/src/xyz/widget.c\n" ""module/core"" && ( set -e ;  /foo/bar/workspace/tools/hosts/platform-x64/compiler/gcc-9.2.0/bin/x86_64-none-linux-gcc  -include /foo/bar/workspace/project/core/engine/sdk/vendor/inc/sysdef.h  -isystem/foo/bar/workspace/tools/hosts/platform-x64/compiler/gcc-9.2.0/lib/gcc/x86_64-none-linux/9.2.0/include -isystem/foo/bar/workspace/tools/hosts/platform-x64/compiler/gcc-9.2.0/lib/gcc/x86_64-none-linux/9.2.0/include-fixed -isystem/foo/bar/workspace/tools/hosts/platform-x64/compiler/gcc-9.2.0/include/c++/9.2.0 -Werror -Wextra -Wshadow -Wcast-align -Wno-unused-parameter -Wno-missing-field-initializers  -fPIC        -g -fno-omit-frame-pointer -fdebug-prefix-map -fstack-protector           -DNDEBUG -DPLATFORM_X64 -DFEATURE_XYZ -DVENDOR_ABC -DCONFIG_TYPE=platform_release_config -D_STRICT_ANSI -D_XOPEN_SOURCE=700 -I_build/platform_x64_release/include/mirror/core/tools/xyz/include -I/foo/bar/workspace/project/core/engine/drivers/common/inc -I/foo/bar/workspace/project/core/engine/drivers/common/inc -isystem/foo/bar/workspace/project/core/engine/drivers/vendor/interface/public/ -fvisibility=hidden -DENABLE_FEATURE_A=1 -DFEATURE_B_SUPPORT=1  -DUSE_NEW_API     -x c         -pedantic -Wno-long-long     -std=c11 -MMD -MP -MT _build/platform_x64_release/widget.o -MF _build/platform_x64_release/widget_dep.mk.tmp -c /foo/bar/workspace/project/core/engine/drivers/module/core/src/xyz/widget.c -o _build/platform_x64_release/widget.o ; /usr/bin/sed -i _build/platform_x64_release/widget_dep.mk.tmp -e ' 1,3s| /foo/bar/workspace/project/core/engine/drivers/module/core/src/xyz/widget.c | |' ; /usr/bin/mv -f _build/platform_x64_release/widget_dep.mk.tmp _build/platform_x64_release/widget_dep.mk )"#;
+
     #[test]
     fn test_parse_gcc_command() {
         let config = Config {
@@ -374,7 +1253,7 @@ mod tests {
         let mut parser = Parser::new(&config).unwrap();
 
         let cmd = "gcc -c test.c -o test.o";
-        let result = parser.parse_line(cmd, &config);
+        let result = parser.parse_line(cmd, &config).unwrap();
 
         assert_eq!(result.len(), 1);
         let cmd = &result[0];
@@ -384,23 +1263,1014 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_build_log() {
-        let dir = tempdir().unwrap();
-        let log_path = dir.path().join("build.log");
+    fn test_parse_quoted_arguments() {
+        let mut config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line(
+                "gcc -DNAME=\"a b\" -DPATH='/x y/z' -isystem/usr/inc -c a.c -o a.o",
+                &config,
+            )
+            .unwrap();
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap()[..4],
+            ["gcc", "-DNAME=a b", "-DPATH=/x y/z", "-isystem/usr/inc"]
+        );
+
+        config.command_style = true;
+        let result = parser
+            .parse_line("gcc -DNAME=\"a b\" -c a.c -o a.o", &config)
+            .unwrap();
+        assert_eq!(
+            result[0].command.as_deref(),
+            Some("gcc '-DNAME=a b' -c a.c -o a.o")
+        );
+
+        // Quotes inside an argument survive the round trip through command style
+        let result = parser
+            .parse_line("gcc '-DMSG=\"hi\"' -c a.c -o a.o", &config)
+            .unwrap();
+        let command = result[0].command.as_deref().unwrap();
+        assert_eq!(split_arguments(command)[..2], ["gcc", "-DMSG=\"hi\""]);
+    }
+
+    #[test]
+    fn test_parse_backslash_paths() {
+        let mut config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        // Unquoted backslashes are path separators, not escapes
+        let result = parser
+            .parse_line(r"clang-cl /c C:\src\foo.c /Fofoo.obj", &config)
+            .unwrap();
+        assert_eq!(result[0].file, r"C:\src\foo.c");
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap()[1..3],
+            ["/c", r"C:\src\foo.c"]
+        );
+
+        let result = parser
+            .parse_line(r"gcc -c sub\dir\a.c -o a.o", &config)
+            .unwrap();
+        assert_eq!(result[0].file, r"sub\dir\a.c");
+        assert_eq!(result[0].arguments.as_ref().unwrap()[2], r"sub\dir\a.c");
+
+        // The quoted command splits back into the same arguments
+        config.command_style = true;
+        let result = parser
+            .parse_line(r"gcc -c sub\dir\a.c -o a.o", &config)
+            .unwrap();
+        let command = result[0].command.as_deref().unwrap();
+        assert_eq!(split_arguments(command)[2], r"sub\dir\a.c");
+    }
+
+    #[test]
+    fn test_rewrite_paths() {
+        let config = Config {
+            no_strict: true,
+            rewrite_paths: vec![
+                (
+                    String::from("/abs/build/out"),
+                    String::from("/portable/out"),
+                ),
+                (String::from("/abs/src"), String::from("/portable/src")),
+            ],
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line(
+                "gcc -I/abs/build/out/gen -isystem /abs/src/third_party -I/usr/include \
+                 -c /abs/src/a.c -o /abs/build/out/a.o",
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result[0].file, "/portable/src/a.c");
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap()[1..],
+            [
+                "-I/portable/out/gen",
+                "-isystem",
+                "/portable/src/third_party",
+                "-I/usr/include",
+                "-c",
+                "/portable/src/a.c",
+                "-o",
+                "/portable/out/a.o",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_fortran_commands() {
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let lines = [
+            (
+                "gfortran -O2 -c solver.f90 -o solver.o",
+                "solver.f90",
+                "gfortran",
+            ),
+            (
+                "/opt/intel/bin/ifort -c -O3 -module mod legacy.for -o legacy.o",
+                "legacy.for",
+                "/opt/intel/bin/ifort",
+            ),
+            ("flang-new -fc kernel.f08", "kernel.f08", "flang-new"),
+            ("nvfortran -acc -c gpu.f95 -o gpu.o", "gpu.f95", "nvfortran"),
+        ];
+        for (line, file, compiler) in lines {
+            let result = parser.parse_line(line, &config).unwrap();
+            assert_eq!(result.len(), 1, "{line}");
+            assert_eq!(result[0].file, file);
+            assert_eq!(result[0].arguments.as_ref().unwrap()[0], compiler);
+        }
+    }
+
+    #[test]
+    fn test_libtool_compile() {
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let mut result = parser
+            .parse_line(
+                "/bin/bash ../libtool --tag=CC --mode=compile gcc -DPIC -c foo.c -o foo.lo",
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].file, "foo.c");
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap(),
+            &["gcc", "-DPIC", "-c", "foo.c", "-o", "foo.lo"]
+        );
+
+        // libtool objects map to plain ones with an extension map
+        crate::map_output_extensions(&mut result, &[(String::from("lo"), String::from("o"))]);
+        assert_eq!(result[0].arguments.as_ref().unwrap()[5], "foo.o");
+    }
+
+    #[test]
+    fn test_custom_compiler_wrapper_regex() {
+        let line = "buildwrap --opt --toolchain=clang gcc -c a.c -o a.o";
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+
+        // The wrapper's own option looks like a compiler without the regex
+        let mut parser = Parser::new(&config).unwrap();
+        let result = parser.parse_line(line, &config).unwrap();
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap()[0],
+            "--toolchain=clang"
+        );
+
+        let config = Config {
+            compiler_wrapper_regex: Some(String::from(r"buildwrap(?:\s+--\S+)*")),
+            ..config
+        };
+        let mut parser = Parser::new(&config).unwrap();
+        let result = parser.parse_line(line, &config).unwrap();
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap(),
+            &["gcc", "-c", "a.c", "-o", "a.o"]
+        );
+    }
+
+    #[test]
+    fn test_parse_cc_cxx_compilers() {
+        let config = Config {
+            no_strict: true,
+            cc: Some(String::from("mycc")),
+            cxx: Some(String::from("ccache /opt/tools/my++")),
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser.parse_line("mycc -c a.c -o a.o", &config).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].arguments.as_ref().unwrap()[0], "mycc");
+
+        let result = parser
+            .parse_line("/opt/tools/my++ -c b.cpp -o b.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+
+        // The default compilers still match
+        let result = parser.parse_line("gcc -c c.c -o c.o", &config).unwrap();
+        assert_eq!(result.len(), 1);
+    }
+
+    #[test]
+    fn test_from_regexes() {
+        let config = Config {
+            no_strict: true,
+            filter_linker_commands: false,
+            ..Config::default()
+        };
+        let compile_regex = Regex::new(r"(?:^|/)tcc(?:\s|$)").unwrap();
+        let file_regex = Regex::new(r"\s(\S+\.c)(?:\s|$)").unwrap();
+
+        for line in ["tcc a.c -o a", "tcc -run b.c"] {
+            let mut parser =
+                Parser::from_regexes(compile_regex.clone(), file_regex.clone(), &config).unwrap();
+            let result = parser.parse_line(line, &config).unwrap();
+            assert_eq!(result.len(), 1, "{line}");
+            assert_eq!(result[0].arguments.as_ref().unwrap()[0], "tcc");
+        }
+    }
+
+    #[test]
+    fn test_filter_linker_commands() {
+        let mut config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let compile_regex =
+            Regex::new(r"(?:^|/)(?:gcc|clang-cl|ld\.lld|arm-none-eabi-ld)(?:\s|$)").unwrap();
+        let file_regex = Regex::new(r"\s(\S+\.[co])(?:\s|$)").unwrap();
+        let mut parser = Parser::from_regexes(compile_regex, file_regex, &config).unwrap();
+
+        let log = "gcc -c a.c -o a.o\n\
+                   ld.lld crt0.o -o app\n\
+                   clang-cl /nologo /c b.c\n\
+                   arm-none-eabi-ld main.o -o fw.elf\n";
+        let commands = parser.parse_reader(log.as_bytes(), &config).unwrap();
+        let files: Vec<_> = commands.iter().map(|cmd| cmd.file.as_str()).collect();
+        assert_eq!(files, ["a.c", "b.c"]);
+
+        config.filter_linker_commands = false;
+        let commands = parser.parse_line("ld.lld crt0.o -o app", &config).unwrap();
+        assert_eq!(commands.len(), 1);
+    }
+
+    #[test]
+    fn test_compile_and_link() {
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser.parse_line("gcc -O2 a.c -o app", &config).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].file, "a.c");
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap(),
+            &["gcc", "-O2", "-c", "a.c", "-o", "app"]
+        );
+
+        // Linking objects alone has no source file
+        let result = parser.parse_line("gcc a.o b.o -o app", &config).unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_parse_clang_variants() {
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line("clang-cl /nologo /c /W4 foo.c /Fofoo.obj", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].file, "foo.c");
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap(),
+            &["clang-cl", "/nologo", "/c", "/W4", "foo.c", "/Fofoo.obj"]
+        );
+
+        for line in [
+            "clang-14 -c a.c -o a.o",
+            "/usr/bin/clang++-14 -c a.cpp -o a.o",
+            "clang-cpp -c a.c -o a.i",
+        ] {
+            let result = parser.parse_line(line, &config).unwrap();
+            assert_eq!(result.len(), 1, "{line}");
+        }
+    }
+
+    #[test]
+    fn test_language_filter() {
+        let config = Config {
+            no_strict: true,
+            languages: vec![Language::Cpp],
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let log = "gcc -c a.c -o a.o\n\
+                   g++ -c b.cpp -o b.o\n\
+                   clang -x c++ -c c.c -o c.o\n";
+        let commands = parser.parse_reader(log.as_bytes(), &config).unwrap();
+        let files: Vec<_> = commands.iter().map(|cmd| cmd.file.as_str()).collect();
+        assert_eq!(files, vec!["b.cpp", "c.c"]);
+        assert_eq!(parser.stats().language_filtered, 1);
+    }
+
+    #[test]
+    fn test_parse_extra_source_extension() {
+        let config = Config {
+            no_strict: true,
+            source_extensions: vec![String::from("ixx")],
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line("g++ -std=c++20 -c foo.ixx -o foo.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].file, "foo.ixx");
+
+        // Default extensions must still be recognized
+        let result = parser
+            .parse_line("gcc -c test.c -o test.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].file, "test.c");
+    }
+
+    #[test]
+    fn test_parse_icecc_wrapper() {
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line("icecc gcc -c foo.c -o foo.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].file, "foo.c");
+        assert_eq!(result[0].arguments.as_ref().unwrap()[0], "gcc");
+
+        let result = parser
+            .parse_line(
+                "ICECC_VERSION=/opt/icecc/gcc-9.tar.gz icecc g++ -c bar.cpp -o bar.o",
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].file, "bar.cpp");
+        assert_eq!(result[0].arguments.as_ref().unwrap()[0], "g++");
+    }
+
+    #[test]
+    fn test_parse_line_detailed_compiler() {
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line_detailed("g++ -c a.cpp -o a.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].0.file, "a.cpp");
+        assert_eq!(result[0].1, "g++");
+    }
+
+    #[test]
+    fn test_parse_build_log() {
+        let dir = tempdir().unwrap();
+        let log_path = dir.path().join("build.log");
+        let mut file = File::create(&log_path).unwrap();
+
+        writeln!(file, "gcc -c test1.c -o test1.o").unwrap();
+        writeln!(file, "gcc -c test2.c -o test2.o").unwrap();
+        writeln!(file, "echo 'Not a compile command'").unwrap();
+
+        let config = Config {
+            no_strict: true, // Don't check for file existence in test
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let commands = parser.parse_file(&log_path, &config).unwrap();
+        assert_eq!(commands.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("keep.c")).unwrap();
+        File::create(dir.path().join("skip.c")).unwrap();
+
+        let log_path = dir.path().join("build.log");
         let mut file = File::create(&log_path).unwrap();
+        writeln!(file, "gcc -c keep.c -o keep.o").unwrap();
+        writeln!(file, "gcc -c skip.c -o skip.o").unwrap();
+        writeln!(file, "gcc -c missing.c -o missing.o").unwrap();
+        writeln!(file, "echo done").unwrap();
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            exclude_patterns: vec![String::from("skip")],
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let (commands, stats) = parser.parse_file_with_stats(&log_path, &config).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            stats,
+            ParseStats {
+                lines_processed: 4,
+                lines_matched: 3,
+                commands_found: 1,
+                excluded: 1,
+                missing_files: 1,
+                compiler_filtered: 0,
+                language_filtered: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_warn_missing_keeps_command() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("a.c")).unwrap();
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            warn_missing: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let mut commands = parser.parse_line("gcc -c a.c -o a.o", &config).unwrap();
+        commands.extend(
+            parser
+                .parse_line("gcc -c gone.c -o gone.o", &config)
+                .unwrap(),
+        );
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[1].file, "gone.c");
+
+        assert_eq!(parser.warnings().len(), 1);
+        let warning = &parser.warnings()[0];
+        assert_eq!(warning.line, 2);
+        assert!(warning.reason.starts_with("source file not found"));
+        assert!(warning.reason.ends_with("gone.c"));
+        assert_eq!(parser.stats().missing_files, 0);
+    }
+
+    #[test]
+    fn test_strict_includes() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("a.c")).unwrap();
+        std::fs::create_dir(dir.path().join("inc")).unwrap();
+        File::create(dir.path().join("inc/config.h")).unwrap();
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            strict_includes: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let found = "gcc -Iinc -include config.h -c a.c -o a.o";
+        assert_eq!(parser.parse_line(found, &config).unwrap().len(), 1);
+
+        let missing = "gcc -include gen/version.h -c a.c -o a.o";
+        assert!(parser.parse_line(missing, &config).unwrap().is_empty());
+        assert_eq!(parser.stats().missing_files, 1);
+
+        let config = Config {
+            warn_missing: true,
+            ..config
+        };
+        assert_eq!(parser.parse_line(missing, &config).unwrap().len(), 1);
+        assert_eq!(
+            parser.warnings()[0].reason,
+            "forced include not found: gen/version.h"
+        );
+    }
+
+    #[test]
+    fn test_full_path_relative_compiler() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("cc")).unwrap();
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            no_strict: true,
+            full_path: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line("./cc -c test.c -o test.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        let expected = dir.path().join("cc").canonicalize().unwrap();
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap()[0],
+            expected.to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn test_full_path_bare_compiler() {
+        let Ok(expected) = which::which("gcc") else {
+            return;
+        };
+
+        let config = Config {
+            no_strict: true,
+            full_path: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line("gcc -c test.c -o test.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap()[0],
+            expected.to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn test_compiler_filter() {
+        let config = Config {
+            no_strict: true,
+            compiler_filter: vec![String::from("clang++")],
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser.parse_line("clang -c a.c -o a.o", &config).unwrap();
+        assert!(result.is_empty());
+
+        let result = parser
+            .parse_line("/usr/bin/clang++ -c b.cpp -o b.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].file, "b.cpp");
+        assert_eq!(parser.stats().compiler_filtered, 1);
+    }
+
+    #[test]
+    fn test_include_patterns() {
+        let config = Config {
+            no_strict: true,
+            include_patterns: vec![String::from("^src/")],
+            exclude_patterns: vec![String::from("generated")],
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+        let log = "gcc -c src/a.c -o a.o\n\
+                   gcc -c test/b.c -o b.o\n\
+                   gcc -c src/generated.c -o generated.o\n";
+
+        let commands = parser.parse_reader(log.as_bytes(), &config).unwrap();
+        let files: Vec<_> = commands.iter().map(|cmd| cmd.file.as_str()).collect();
+        assert_eq!(files, ["src/a.c"]);
+        assert_eq!(parser.stats().excluded, 2);
+    }
+
+    #[test]
+    fn test_exclude_dir() {
+        let config = Config {
+            no_strict: true,
+            exclude_dir_patterns: vec![String::from("third_party/")],
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        parser
+            .parse_line(
+                "make[1]: Entering directory '/build/third_party/x'",
+                &config,
+            )
+            .unwrap();
+        let result = parser.parse_line("gcc -c x.c -o x.o", &config).unwrap();
+        assert!(result.is_empty());
+        parser
+            .parse_line("make[1]: Leaving directory '/build/third_party/x'", &config)
+            .unwrap();
+
+        parser
+            .parse_line("make[1]: Entering directory '/build/src'", &config)
+            .unwrap();
+        let result = parser
+            .parse_line("gcc -c main.c -o main.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].directory, "/build/src");
+    }
+
+    #[test]
+    fn test_max_consecutive_non_matching() {
+        let config = Config {
+            no_strict: true,
+            max_consecutive_non_matching: Some(2),
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
 
-        writeln!(file, "gcc -c test1.c -o test1.o").unwrap();
-        writeln!(file, "gcc -c test2.c -o test2.o").unwrap();
-        writeln!(file, "echo 'Not a compile command'").unwrap();
+        assert!(parser.try_parse_line("echo one", &config).is_ok());
+        // Empty lines and directory messages do not count
+        assert!(parser.try_parse_line("", &config).is_ok());
+        assert!(
+            parser
+                .try_parse_line("make[1]: Entering directory '/tmp'", &config)
+                .is_ok()
+        );
+        // A compile command resets the run
+        assert!(
+            parser
+                .try_parse_line("gcc -c test.c -o test.o", &config)
+                .is_ok()
+        );
+        assert!(parser.try_parse_line("echo two", &config).is_ok());
+
+        match parser.try_parse_line("echo three", &config) {
+            Err(CompileDbError::ParseFailure {
+                line,
+                content,
+                reason,
+            }) => {
+                assert_eq!(line, 6);
+                assert_eq!(content, "echo three");
+                assert!(reason.contains("2 consecutive lines"));
+            }
+            other => panic!("expected ParseFailure, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_failure_collected_as_warning() {
+        let dir = tempdir().unwrap();
+        let log_path = dir.path().join("build.log");
+        let mut file = File::create(&log_path).unwrap();
+        writeln!(file, "gcc -c good.c -o good.o").unwrap();
+        writeln!(file, "gcc -c generated.zz -o generated.o").unwrap();
+        drop(file);
 
+        // The second alternative matches without capturing a file
         let config = Config {
-            no_strict: true, // Don't check for file existence in test
+            no_strict: true,
+            regex_file: String::from(r"\s-c\s+(?:(\S+\.c)|\S+\.zz)\s"),
             ..Config::default()
         };
+
         let mut parser = Parser::new(&config).unwrap();
+        match parser.parse_line("gcc -c generated.zz -o generated.o", &config) {
+            Err(CompileDbError::ParseFailure { line, reason, .. }) => {
+                assert_eq!(line, 1);
+                assert!(reason.contains("captured no source file"));
+            }
+            other => panic!("expected ParseFailure, got {other:?}"),
+        }
 
+        let mut parser = Parser::new(&config).unwrap();
         let commands = parser.parse_file(&log_path, &config).unwrap();
-        assert_eq!(commands.len(), 2);
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            parser.warnings(),
+            [ParseWarning {
+                line: 2,
+                content: String::from("gcc -c generated.zz -o generated.o"),
+                reason: String::from("file regex matched but captured no source file"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_skip_decoy_fragments() {
+        let config = Config {
+            no_strict: true,
+            build_dir: PathBuf::from("/src"),
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let commands = parser
+            .parse_line("set -e ; export CC=gcc ; gcc -c a.c -o a.o", &config)
+            .unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].file, "a.c");
+        assert_eq!(commands[0].arguments.as_ref().unwrap()[0], "gcc");
+    }
+
+    #[test]
+    fn test_parse_reader_collecting_errors() {
+        let log: &[u8] =
+            b"gcc -c a.c -o a.o\n\xff\xfe\nmake CC=gcc -c x.c -o x.o\ngcc -c b.c -o b.o\n";
+
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let (commands, errors) = parser.parse_reader_collecting(log, &config);
+        let files: Vec<_> = commands.iter().map(|cmd| cmd.file.as_str()).collect();
+        assert_eq!(files, vec!["a.c", "b.c"]);
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].0, 2);
+        assert!(matches!(errors[0].1, CompileDbError::Io(_)));
+        assert_eq!(errors[1].0, 3);
+        assert!(matches!(
+            &errors[1].1,
+            CompileDbError::ParseFailure { line: 3, reason, .. } if reason.contains("compile regex")
+        ));
+    }
+
+    #[test]
+    fn test_keep_going_on_invalid_utf8() {
+        let log: &[u8] = b"gcc -c a.c -o a.o\n\xff\xfe garbage\ngcc -c b.c -o b.o\n";
+
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+        let result = parser.for_each_command(log, &config, |_| Ok(()));
+        assert!(matches!(result, Err(CompileDbError::Io(_))));
+
+        let config = Config {
+            keep_going: true,
+            ..config
+        };
+        let mut parser = Parser::new(&config).unwrap();
+        let mut files = Vec::new();
+        parser
+            .for_each_command(log, &config, |cmd| {
+                files.push(cmd.file);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(files, vec!["a.c", "b.c"]);
+    }
+
+    #[test]
+    fn test_parse_reader_in_memory() {
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let log = "make[1]: Entering directory '/build/lib'\ngcc -c util.c -o util.o\n";
+        let commands = parser.parse_reader(log.as_bytes(), &config).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].directory, "/build/lib");
+        assert_eq!(commands[0].file, "util.c");
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let log = "make[1]: Entering directory '/build/lib'\ngcc -c util.c -o util.o\n";
+        let commands = parser.parse_lines(log.lines(), &config).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].directory, "/build/lib");
+
+        let lines = vec![String::from("gcc -c a.c -o a.o"), String::from("echo done")];
+        let commands = parser.parse_lines(lines, &config).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].file, "a.c");
+        assert_eq!(parser.stats().commands_found, 2);
+    }
+
+    #[test]
+    fn test_expand_env_vars_in_paths() {
+        // Cargo sets CARGO_PKG_NAME for test binaries
+        let config = Config {
+            no_strict: true,
+            build_dir: PathBuf::from("/work/${CARGO_PKG_NAME}"),
+            expand_env_vars: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line(
+                "gcc -c $CARGO_PKG_NAME/$COMPILEDB_UNSET_TEST_VAR.c -o a.o",
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result[0].directory, "/work/compiledb");
+        assert_eq!(result[0].file, "compiledb/$COMPILEDB_UNSET_TEST_VAR.c");
+    }
+
+    #[test]
+    fn test_expand_make_vars() {
+        let mut config = Config {
+            no_strict: true,
+            build_dir: PathBuf::from("/work"),
+            expand_make_vars: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line("gcc -I${CURDIR}/include -c $(CURDIR)/a.c -o a.o", &config)
+            .unwrap();
+        assert_eq!(result[0].file, "a.c");
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap(),
+            &["gcc", "-I/work/include", "-c", "a.c", "-o", "a.o"]
+        );
+
+        config.expand_make_vars = false;
+        let result = parser
+            .parse_line("gcc -c $(CURDIR)/a.c -o a.o", &config)
+            .unwrap();
+        assert_eq!(result[0].file, "$(CURDIR)/a.c");
+    }
+
+    #[test]
+    fn test_warn_no_enter_dir() {
+        let log = "gcc -c a.c -o a.o\n".repeat(NO_ENTER_DIR_WARN_LINES);
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+
+        let mut parser = Parser::new(&config).unwrap();
+        parser.parse_reader(log.as_bytes(), &config).unwrap();
+        assert!(parser.warned_no_enter_dir);
+
+        let mut parser = Parser::new(&config).unwrap();
+        parser
+            .parse_line("make[1]: Entering directory '/build'", &config)
+            .unwrap();
+        parser.parse_reader(log.as_bytes(), &config).unwrap();
+        assert!(!parser.warned_no_enter_dir);
+
+        let config = Config {
+            warn_no_enter_dir: false,
+            ..config
+        };
+        let mut parser = Parser::new(&config).unwrap();
+        parser.parse_reader(log.as_bytes(), &config).unwrap();
+        assert!(!parser.warned_no_enter_dir);
+    }
+
+    #[test]
+    fn test_make_c_without_print_directory() {
+        // Without Entering/Leaving lines the end of a sub-make is unknown, so
+        // `make -C sub` is taken as nested only because `lib/sub` exists on
+        // disk, while `make -C app`, with no `lib/app`, goes back to the root.
+        // The directories must exist for the log to resolve.
+        let dir = tempdir().unwrap();
+        for file in ["lib/util.c", "lib/sub/deep.c", "app/main.c"] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        let log = "make -C lib all\n\
+                   gcc -c util.c -o util.o\n\
+                   make -C sub all\n\
+                   gcc -c deep.c -o deep.o\n\
+                   make -C app all\n\
+                   gcc -c main.c -o main.o\n";
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+        let commands = parser.parse_reader(log.as_bytes(), &config).unwrap();
+
+        let units: Vec<_> = commands
+            .iter()
+            .map(|cmd| Path::new(&cmd.directory).join(&cmd.file))
+            .collect();
+        assert_eq!(
+            units,
+            ["lib/util.c", "lib/sub/deep.c", "app/main.c"].map(|file| dir.path().join(file))
+        );
+        assert!(!parser.warned_unresolved);
+
+        // Without any directory information, unresolved sources are reported once
+        let mut parser = Parser::new(&config).unwrap();
+        parser
+            .parse_reader("gcc -c util.c -o util.o\n".as_bytes(), &config)
+            .unwrap();
+        assert!(parser.warned_unresolved);
+    }
+
+    #[test]
+    fn test_make_c_quoted_dir() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub dir")).unwrap();
+        File::create(dir.path().join("sub dir/util.c")).unwrap();
+
+        let config = Config {
+            build_dir: dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        for line in ["make -C \"sub dir\" all", "make -C 'sub dir' all"] {
+            let mut parser = Parser::new(&config).unwrap();
+            assert!(parser.parse_line(line, &config).unwrap().is_empty());
+            assert_eq!(parser.working_dir, dir.path().join("sub dir"));
+
+            let commands = parser
+                .parse_line("gcc -c util.c -o util.o", &config)
+                .unwrap();
+            assert_eq!(commands.len(), 1);
+            assert_eq!(
+                Path::new(&commands[0].directory),
+                dir.path().join("sub dir")
+            );
+        }
+    }
+
+    #[test]
+    fn test_filter_msvc_includes() {
+        let log = "cl /c /showIncludes main.c\n\
+                   Note: including file: C:\\include\\stdio.h\n\
+                   Note: including file:  C:\\include\\corecrt.h\n\
+                   Note: including file: C:\\include\\vcruntime.h\n";
+        let config = Config {
+            no_strict: true,
+            max_consecutive_non_matching: Some(3),
+            ..Config::default()
+        };
+
+        // The notes neither produce entries nor count as unrecognized lines
+        let mut parser = Parser::new(&config).unwrap();
+        assert!(parser.parse_reader(log.as_bytes(), &config).is_ok());
+
+        let config = Config {
+            filter_msvc_includes: false,
+            ..config
+        };
+        let mut parser = Parser::new(&config).unwrap();
+        assert!(parser.parse_reader(log.as_bytes(), &config).is_err());
+    }
+
+    #[test]
+    fn test_streamed_output_matches_buffered() {
+        let dir = tempdir().unwrap();
+        let log_path = dir.path().join("build.log");
+        let mut file = File::create(&log_path).unwrap();
+        for i in 0..1000 {
+            writeln!(file, "gcc -DN={i} -c src{i}.c -o src{i}.o").unwrap();
+        }
+        drop(file);
+
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+
+        let commands = Parser::new(&config)
+            .unwrap()
+            .parse_file(&log_path, &config)
+            .unwrap();
+        let buffered = serde_json::to_vec_pretty(&commands).unwrap();
+
+        let mut writer = crate::writer::StreamingJsonWriter::new(Vec::new());
+        writer.begin().unwrap();
+        Parser::new(&config)
+            .unwrap()
+            .for_each_command(
+                BufReader::new(File::open(&log_path).unwrap()),
+                &config,
+                |cmd| writer.write_entry(&cmd),
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(writer.entries_written(), 1000);
+        assert_eq!(writer.into_inner(), buffered);
     }
 
     #[test]
@@ -413,12 +2283,16 @@ mod tests {
         let initial_dir = parser.working_dir.clone();
 
         // Test make enter directory
-        let result = parser.parse_line("make[1]: Entering directory '/path/to/src'", &config);
+        let result = parser
+            .parse_line("make[1]: Entering directory '/path/to/src'", &config)
+            .unwrap();
         assert_eq!(result.len(), 0);
         assert_eq!(parser.working_dir, PathBuf::from("/path/to/src"));
 
         // Test make leave directory
-        let result = parser.parse_line("make[1]: Leaving directory '/path/to/src'", &config);
+        let result = parser
+            .parse_line("make[1]: Leaving directory '/path/to/src'", &config)
+            .unwrap();
         assert_eq!(result.len(), 0);
         assert_eq!(parser.working_dir, initial_dir);
     }
@@ -433,10 +2307,28 @@ mod tests {
 
         // Test command with backticks
         let cmd = "gcc -c `echo test.c` -o test.o";
-        let result = parser.parse_line(cmd, &config);
+        let result = parser.parse_line(cmd, &config).unwrap();
         assert_eq!(result.len(), 1);
         let cmd = &result[0];
         assert_eq!(cmd.file, "test.c");
+
+        // Test command with $(...) substitution
+        let cmd = "gcc $(echo -DFOO=1 -Iinc) -c `echo foo.c` -o foo.o";
+        let result = parser.parse_line(cmd, &config).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap(),
+            &["gcc", "-DFOO=1", "-Iinc", "-c", "foo.c", "-o", "foo.o"]
+        );
+
+        // An unexpanded make variable is kept and does not stop later substitutions
+        let cmd = "gcc $(CFLAGS) $(echo -DBAR=1) -c bar.c -o bar.o";
+        let result = parser.parse_line(cmd, &config).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap(),
+            &["gcc", "$(CFLAGS)", "-DBAR=1", "-c", "bar.c", "-o", "bar.o"]
+        );
     }
 
     #[test]
@@ -449,9 +2341,162 @@ mod tests {
         let initial_dir = parser.working_dir.clone();
 
         // Test cd command
-        let result = parser.parse_line("cd src && gcc -c test.c -o test.o", &config);
+        let result = parser
+            .parse_line("cd src && gcc -c test.c -o test.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(
+            result[0].directory,
+            initial_dir.join("src").to_string_lossy()
+        );
+
+        // The cd only applies to its own line
+        let result = parser.parse_line("gcc -c y.c -o y.o", &config).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].directory, initial_dir.to_string_lossy());
+        assert_eq!(parser.working_dir, initial_dir);
+
+        // pushd/popd are scoped within the line
+        let result = parser
+            .parse_line(
+                "pushd lib && gcc -c a.c -o a.o ; popd ; gcc -c b.c -o b.o",
+                &config,
+            )
+            .unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(
+            result[0].directory,
+            initial_dir.join("lib").to_string_lossy()
+        );
+        assert_eq!(result[1].directory, initial_dir.to_string_lossy());
+
+        // A standalone cd line persists
+        let result = parser.parse_line("cd build", &config).unwrap();
+        assert!(result.is_empty());
+        assert_eq!(parser.working_dir, initial_dir.join("build"));
+    }
+
+    #[test]
+    fn test_cd_tilde() {
+        let Some(home) = std::env::var_os("HOME") else {
+            return;
+        };
+
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line("cd ~ && gcc -c a.c -o a.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        // Compare strings: PathBuf equality would ignore a trailing separator
+        assert_eq!(result[0].directory, PathBuf::from(&home).to_string_lossy());
+
+        // Only a leading tilde is expanded
+        let result = parser
+            .parse_line("cd ~/src/~old && gcc -c a.c -o a.o", &config)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(
+            result[0].directory,
+            PathBuf::from(&home).join("src/~old").to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn test_strip_dependency_flags_from_complex_command() {
+        if cfg!(target_os = "windows") {
+            return;
+        }
+
+        let config = Config {
+            no_strict: true,
+            strip_deps: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+        parser.set_working_dir(PathBuf::from(
+            "/foo/bar/workspace/project/core/engine/drivers/module",
+        ));
+
+        let result = parser.parse_line(COMPLEX_COMMAND, &config).unwrap();
         assert_eq!(result.len(), 1);
-        assert_eq!(parser.working_dir, initial_dir.join("src"));
+
+        let args = result[0].arguments.as_ref().unwrap();
+        for flag in ["-MMD", "-MP", "-MT", "-MF"] {
+            assert!(!args.iter().any(|arg| arg == flag), "{flag} not stripped");
+        }
+        assert!(!args.iter().any(|arg| arg.contains("widget_dep.mk.tmp")));
+        assert_eq!(
+            args[args.len() - 4..],
+            [
+                "-c",
+                "core/src/xyz/widget.c",
+                "-o",
+                "_build/platform_x64_release/widget.o"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dedup_includes_from_complex_command() {
+        if cfg!(target_os = "windows") {
+            return;
+        }
+
+        let config = Config {
+            no_strict: true,
+            dedup_includes: true,
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser.parse_line(COMPLEX_COMMAND, &config).unwrap();
+        let args = result[0].arguments.as_ref().unwrap();
+        let includes: Vec<_> = args
+            .iter()
+            .filter(|arg| arg.starts_with("-I"))
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            includes,
+            vec![
+                "-I_build/platform_x64_release/include/mirror/core/tools/xyz/include",
+                "-I/foo/bar/workspace/project/core/engine/drivers/common/inc",
+            ]
+        );
+        assert_eq!(
+            args.iter()
+                .filter(|arg| arg.starts_with("-isystem"))
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_expand_response_file_in_build_dir() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("includes.rsp"), "-Iinc -MD\r\n-DFOO\r\n").unwrap();
+
+        let config = Config {
+            no_strict: true,
+            strip_deps: true,
+            expand_response_files: true,
+            build_dir: dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let mut parser = Parser::new(&config).unwrap();
+
+        let result = parser
+            .parse_line("gcc @includes.rsp -c a.c -o a.o", &config)
+            .unwrap();
+        assert_eq!(
+            result[0].arguments.as_ref().unwrap(),
+            &["gcc", "-Iinc", "-DFOO", "-c", "a.c", "-o", "a.o"]
+        );
     }
 
     #[test]
@@ -462,22 +2507,22 @@ mod tests {
             return;
         }
 
-        // enable logging, since log defaults to silent
-        unsafe {
-            std::env::set_var("RUST_LOG", "debug");
-        }
-        env_logger::init();
+        // enable logging, since tracing defaults to silent
+        let _ = tracing_subscriber::fmt()
+            .with_env_filter("debug")
+            .with_test_writer()
+            .try_init();
 
         let config = Config {
             no_strict: true,
             ..Config::default()
         };
         let mut parser = Parser::new(&config).unwrap();
-        parser.working_dir = PathBuf::from("/foo/bar/workspace/project/core/engine/drivers/module");
-
-        let complex_cmd = r#"/usr/bin/printf " [ %-17.17s ]  CC           drivers/module/core/src/xyz/widget.c\n" ""module/core"" && ( set -e ;  /foo/bar/workspace/tools/hosts/platform-x64/compiler/gcc-9.2.0/bin/x86_64-none-linux-gcc  -include /foo/bar/workspace/project/core/engine/sdk/vendor/inc/sysdef.h  -isystem/foo/bar/workspace/tools/hosts/platform-x64/compiler/gcc-9.2.0/lib/gcc/x86_64-none-linux/9.2.0/include -isystem/foo/bar/workspace/tools/hosts/platform-x64/compiler/gcc-9.2.0/lib/gcc/x86_64-none-linux/9.2.0/include-fixed -isystem/foo/bar/workspace/tools/hosts/platform-x64/compiler/gcc-9.2.0/include/c++/9.2.0 -Werror -Wextra -Wshadow -Wcast-align -Wno-unused-parameter -Wno-missing-field-initializers  -fPIC        -g -fno-omit-frame-pointer -fdebug-prefix-map -fstack-protector           -DNDEBUG -DPLATFORM_X64 -DFEATURE_XYZ -DVENDOR_ABC -DCONFIG_TYPE=platform_release_config -D_STRICT_ANSI -D_XOPEN_SOURCE=700 -I_build/platform_x64_release/include/mirror/core/tools/xyz/include -I/foo/bar/workspace/project/core/engine/drivers/common/inc -I/foo/bar/workspace/project/core/engine/drivers/common/inc -isystem/foo/bar/workspace/project/core/engine/drivers/vendor/interface/public/ -fvisibility=hidden -DENABLE_FEATURE_A=1 -DFEATURE_B_SUPPORT=1  -DUSE_NEW_API     -x c         -pedantic -Wno-long-long     -std=c11 -MMD -MP -MT _build/platform_x64_release/widget.o -MF _build/platform_x64_release/widget_dep.mk.tmp -c /foo/bar/workspace/project/core/engine/drivers/module/core/src/xyz/widget.c -o _build/platform_x64_release/widget.o ; /usr/bin/sed -i _build/platform_x64_release/widget_dep.mk.tmp -e ' 1,3s| /foo/bar/workspace/project/core/engine/drivers/module/core/src/xyz/widget.c | |' ; /usr/bin/mv -f _build/platform_x64_release/widget_dep.mk.tmp _build/platform_x64_release/widget_dep.mk )"#;
+        parser.set_working_dir(PathBuf::from(
+            "/foo/bar/workspace/project/core/engine/drivers/module",
+        ));
 
-        let result = parser.parse_line(complex_cmd, &config);
+        let result = parser.parse_line(COMPLEX_COMMAND, &config).unwrap();
         assert_eq!(result.len(), 1, "Parser did not find any commands");
 
         let cmd = &result[0];
@@ -0,0 +1,173 @@
+use std::path::Path;
+use tracing::{debug, warn};
+
+/// Quoting rules used to split the contents of an `@file` response file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFileQuoting {
+    /// GCC/Clang: POSIX-shell-like quoting where backslash escapes any character
+    Gnu,
+
+    /// `cl.exe`: backslashes are literal unless they precede a double quote
+    Msvc,
+}
+
+impl ResponseFileQuoting {
+    /// Pick the quoting rules for a compiler, e.g. `cl.exe` and `clang-cl` use MSVC rules
+    pub fn for_compiler(compiler: &str) -> Self {
+        // Split on both separators, Windows paths show up in logs parsed on Unix too
+        let name = compiler
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match name.trim_end_matches(".exe") {
+            "cl" | "clang-cl" => ResponseFileQuoting::Msvc,
+            _ => ResponseFileQuoting::Gnu,
+        }
+    }
+
+    /// Split response file contents into arguments. CRLF line endings are
+    /// treated like any other whitespace.
+    pub fn split(self, contents: &str) -> Vec<String> {
+        match self {
+            ResponseFileQuoting::Gnu => {
+                let contents = contents.replace('\r', " ");
+                shlex::split(&contents)
+                    .unwrap_or_else(|| contents.split_whitespace().map(String::from).collect())
+            }
+            ResponseFileQuoting::Msvc => split_msvc(contents),
+        }
+    }
+}
+
+/// Replace every `@file` argument after the compiler with the arguments read
+/// from that file, resolved against `directory`. Files that cannot be read are
+/// left in place.
+pub fn expand_response_files(args: Vec<String>, directory: &Path) -> Vec<String> {
+    let Some(compiler) = args.first() else {
+        return args;
+    };
+    let quoting = ResponseFileQuoting::for_compiler(compiler);
+
+    let mut result = Vec::with_capacity(args.len());
+    for (index, arg) in args.into_iter().enumerate() {
+        let path = match arg.strip_prefix('@') {
+            Some(path) if index > 0 && !path.is_empty() => directory.join(path),
+            _ => {
+                result.push(arg);
+                continue;
+            }
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                debug!("Expanding response file {}", path.display());
+                result.extend(quoting.split(&contents));
+            }
+            Err(e) => {
+                warn!("Cannot read response file {}: {e}", path.display());
+                result.push(arg);
+            }
+        }
+    }
+    result
+}
+
+/// Split following the MSVC command-line rules: `2n` backslashes before a
+/// quote become `n` backslashes and toggle quoting, `2n + 1` become `n`
+/// backslashes and a literal quote, and other backslashes are literal.
+fn split_msvc(contents: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quoted = false;
+    let mut backslashes = 0;
+
+    for c in contents.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                in_arg = true;
+                continue;
+            }
+            '"' => {
+                current.extend(std::iter::repeat_n('\\', backslashes / 2));
+                if backslashes % 2 == 1 {
+                    current.push('"');
+                } else {
+                    quoted = !quoted;
+                }
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                current.extend(std::iter::repeat_n('\\', backslashes));
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.extend(std::iter::repeat_n('\\', backslashes));
+                current.push(c);
+                in_arg = true;
+            }
+        }
+        backslashes = 0;
+    }
+    current.extend(std::iter::repeat_n('\\', backslashes));
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_msvc_response_file_with_crlf() {
+        let contents =
+            "/nologo\r\n/I \"C:\\Program Files\\x\"\r\n/DMSG=\\\"hi\\\" C:\\src\\a.c\r\n";
+        assert_eq!(
+            ResponseFileQuoting::Msvc.split(contents),
+            [
+                "/nologo",
+                "/I",
+                "C:\\Program Files\\x",
+                "/DMSG=\"hi\"",
+                "C:\\src\\a.c"
+            ]
+        );
+        assert_eq!(
+            ResponseFileQuoting::for_compiler("C:\\VC\\bin\\CL.EXE"),
+            ResponseFileQuoting::Msvc
+        );
+    }
+
+    #[test]
+    fn test_expand_gnu_response_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("opts.rsp"),
+            "-DNAME=\"a b\"\r\n-I'inc dir' -O2\r\n",
+        )
+        .unwrap();
+
+        let args = ["gcc", "@opts.rsp", "@missing.rsp", "-c", "a.c"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            expand_response_files(args, dir.path()),
+            [
+                "gcc",
+                "-DNAME=a b",
+                "-Iinc dir",
+                "-O2",
+                "@missing.rsp",
+                "-c",
+                "a.c"
+            ]
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use crate::parser::Parser;
+use crate::{CompileCommand, CompileDbError, Config, DEFAULT_REGEX_FILE, expand_tilde};
+use regex::Regex;
+use std::{
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tracing::{info, info_span};
+
+/// SCons' default `CCCOM`/`CXXCOM` put the sources last (`-o $TARGET -c
+/// $FLAGS $SOURCES`), unlike the `-c <file> -o` order make rules use
+const SCONS_FILE_REGEX: &str =
+    r"\s-c\s(?:.*?\s)?(\S+\.(?:c|cpp|cc|cxx|c\+\+|s|m|mm|cu|f|f90|f95|f03|f08|for|fpp))(?:\s|$)";
+
+/// Parser for the output of an SCons build.
+///
+/// SCons echoes every command it runs, interleaved with `scons: ...` status
+/// messages and, with `--tree=all`, the dependency tree. Status and tree lines
+/// are skipped (except `Entering directory`, printed for `-C`/`-u`), and the
+/// remaining lines go through the regular make-output [`Parser`]. Unless a
+/// custom `regex_file` is configured, sources are found in SCons' order.
+pub struct SconsParser {
+    enter_dir_regex: Regex,
+    tree_regex: Regex,
+    parser: Parser,
+}
+
+impl SconsParser {
+    pub fn new(config: &Config) -> Result<Self, CompileDbError> {
+        let mut config = config.clone();
+        if config.regex_file == DEFAULT_REGEX_FILE {
+            config.regex_file = SCONS_FILE_REGEX.to_string();
+        }
+
+        Ok(Self {
+            enter_dir_regex: Regex::new(r#"^scons: Entering directory ['`"](.*)['`"]$"#).unwrap(),
+            tree_regex: Regex::new(r"^\s*(?:\[[ A-Z]*\]\s*)?[| ]*\+-").unwrap(),
+            parser: Parser::new(&config)?,
+        })
+    }
+
+    /// Parse a single line of SCons output
+    pub fn parse_line(
+        &mut self,
+        line: &str,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        if let Some(caps) = self.enter_dir_regex.captures(line) {
+            self.parser.set_working_dir(PathBuf::from(&caps[1]));
+            return Ok(Vec::new());
+        }
+        if line.starts_with("scons: ") || self.tree_regex.is_match(line) {
+            return Ok(Vec::new());
+        }
+        self.parser.try_parse_line(line, config)
+    }
+
+    /// Parse an SCons log file and extract compilation commands
+    pub fn parse_file(
+        &mut self,
+        path: &Path,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        let _span = info_span!("parse_scons_file", path = ?path).entered();
+        let file = std::fs::File::open(path)?;
+        self.parse_reader(BufReader::new(file), config)
+    }
+
+    /// Parse SCons output from any buffered reader
+    pub fn parse_reader<R: BufRead>(
+        &mut self,
+        reader: R,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        let mut commands = Vec::new();
+        for line in reader.lines() {
+            let line = line.map_err(CompileDbError::Io)?;
+            commands.extend(self.parse_line(&line, config)?);
+        }
+        info!("Found {} compilation commands", commands.len());
+        Ok(commands)
+    }
+}
+
+/// Run `scons` with the given arguments and parse the commands it prints.
+///
+/// SCons only echoes the commands of out-of-date targets, so run `scons -c`
+/// first for a complete database. With `no_build`, `-n` is passed so nothing
+/// is actually compiled.
+pub fn run_scons(args: &[String], config: &Config) -> Result<Vec<CompileCommand>, CompileDbError> {
+    info!("Executing scons with arguments: {:?}", args);
+
+    let mut command = Command::new("scons");
+    if config.no_build {
+        command.arg("-n");
+    }
+    let mut child = command
+        .args(args)
+        .current_dir(expand_tilde(&config.build_dir))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| CompileDbError::MakeError("Failed to capture scons stdout".to_string()))?;
+
+    let mut parser = SconsParser::new(config)?;
+    let commands = parser.parse_reader(BufReader::new(stdout), config)?;
+
+    let status = child
+        .wait()
+        .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+    if !status.success() {
+        return Err(CompileDbError::MakeError(
+            "scons command failed".to_string(),
+        ));
+    }
+
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCONS_LOG: &str = "scons: Reading SConscript files ...
+scons: done reading SConscript files.
+scons: Entering directory `/src/project'
+scons: Building targets ...
+gcc -o main.o -c -O2 main.c
+g++ -o lib/util.o -c -Iinclude lib/util.cpp
+gcc -o app main.o lib/util.o
++-.
+  +-SConstruct
+  +-app
+  | +-main.o
+  | | +-main.c
+  | | +-/usr/bin/gcc
+[E b   C  ]  +-main.c
+scons: done building targets.
+";
+
+    #[test]
+    fn test_parse_scons_output() {
+        let config = Config {
+            no_strict: true,
+            build_dir: PathBuf::from("/elsewhere"),
+            ..Config::default()
+        };
+        let mut parser = SconsParser::new(&config).unwrap();
+
+        let commands = parser.parse_reader(SCONS_LOG.as_bytes(), &config).unwrap();
+        assert_eq!(commands.len(), 2);
+
+        assert_eq!(commands[0].directory, "/src/project");
+        assert_eq!(commands[0].file, "main.c");
+        assert_eq!(commands[1].file, "lib/util.cpp");
+        assert_eq!(
+            commands[1].arguments.as_ref().unwrap(),
+            &["g++", "-o", "lib/util.o", "-c", "-Iinclude", "lib/util.cpp"]
+        );
+    }
+}
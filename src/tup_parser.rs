@@ -0,0 +1,127 @@
+use crate::parser::Parser;
+use crate::{CompileCommand, CompileDbError, Config, expand_tilde};
+use regex::Regex;
+use std::{
+    io::{BufRead, BufReader},
+    path::Path,
+    process::{Command, Stdio},
+};
+use tracing::{info, info_span};
+
+/// Parser for the command lines printed by `tup monitor --show-commands`.
+///
+/// Each command is printed on its own line as `[cmd] <command_line>`; other
+/// lines are progress output and are ignored. Extracted commands go through
+/// the regular make-output [`Parser`], so all filtering options apply.
+pub struct TupParser {
+    cmd_regex: Regex,
+    parser: Parser,
+}
+
+impl TupParser {
+    pub fn new(config: &Config) -> Result<Self, CompileDbError> {
+        Ok(Self {
+            cmd_regex: Regex::new(r"^\s*\[cmd\]\s+(.*)$").unwrap(),
+            parser: Parser::new(config)?,
+        })
+    }
+
+    /// Parse a single line of `tup` output
+    pub fn parse_line(
+        &mut self,
+        line: &str,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        match self.cmd_regex.captures(line) {
+            Some(caps) => self.parser.try_parse_line(&caps[1], config),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Parse a `tup` log file and extract compilation commands
+    pub fn parse_file(
+        &mut self,
+        path: &Path,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        let _span = info_span!("parse_tup_file", path = ?path).entered();
+        let file = std::fs::File::open(path)?;
+        self.parse_reader(BufReader::new(file), config)
+    }
+
+    /// Parse `tup` output from any buffered reader
+    pub fn parse_reader<R: BufRead>(
+        &mut self,
+        reader: R,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        let mut commands = Vec::new();
+        for line in reader.lines() {
+            let line = line.map_err(CompileDbError::Io)?;
+            commands.extend(self.parse_line(&line, config)?);
+        }
+        info!("Found {} compilation commands", commands.len());
+        Ok(commands)
+    }
+}
+
+/// Run `tup monitor --show-commands` for the given targets and parse its output
+pub fn run_tup(args: &[String], config: &Config) -> Result<Vec<CompileCommand>, CompileDbError> {
+    info!("Executing tup with targets: {:?}", args);
+
+    let mut child = Command::new("tup")
+        .args(["monitor", "--show-commands", "--"])
+        .args(args)
+        .current_dir(expand_tilde(&config.build_dir))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| CompileDbError::MakeError("Failed to capture tup stdout".to_string()))?;
+
+    let mut parser = TupParser::new(config)?;
+    let commands = parser.parse_reader(BufReader::new(stdout), config)?;
+
+    let status = child
+        .wait()
+        .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+    if !status.success() {
+        return Err(CompileDbError::MakeError("tup command failed".to_string()));
+    }
+
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TUP_LOG: &str = "[ tup ] [0.001s] Scanning filesystem...
+[cmd] gcc -c main.c -o main.o
+ 1) [0.012s] CC main.c
+[cmd] cd lib && g++ -O2 -c util.cpp -o util.o
+[cmd] gcc main.o lib/util.o -o app
+";
+
+    #[test]
+    fn test_parse_tup_output() {
+        let config = Config {
+            no_strict: true,
+            build_dir: std::path::PathBuf::from("/src"),
+            ..Config::default()
+        };
+        let mut parser = TupParser::new(&config).unwrap();
+
+        let commands = parser.parse_reader(TUP_LOG.as_bytes(), &config).unwrap();
+        assert_eq!(commands.len(), 2);
+
+        assert_eq!(commands[0].directory, "/src");
+        assert_eq!(commands[0].file, "main.c");
+        assert_eq!(commands[1].directory, "/src/lib");
+        assert_eq!(commands[1].file, "util.cpp");
+    }
+}
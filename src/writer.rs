@@ -0,0 +1,163 @@
+use crate::{CompileCommand, CompileDbError, OutputFormat};
+use std::io::Write;
+
+/// Writes a compilation database incrementally, one entry at a time.
+///
+/// The output is byte-identical to [`write_commands`](crate::write_commands)
+/// on the full list of entries, but only the entry being written is held in
+/// memory.
+pub struct StreamingJsonWriter<W: Write> {
+    inner: W,
+    format: OutputFormat,
+    entries_written: usize,
+    flush_each_entry: bool,
+}
+
+impl<W: Write> StreamingJsonWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_format(inner, OutputFormat::Json)
+    }
+
+    /// Create a writer producing the given output format
+    pub fn with_format(inner: W, format: OutputFormat) -> Self {
+        Self {
+            inner,
+            format,
+            entries_written: 0,
+            flush_each_entry: false,
+        }
+    }
+
+    /// Flush the underlying writer after every entry, so that a reader
+    /// following the output sees each entry as soon as it is parsed
+    pub fn flush_each_entry(mut self, flush: bool) -> Self {
+        self.flush_each_entry = flush;
+        self
+    }
+
+    /// Open the JSON array (nothing to do for JSON Lines)
+    pub fn begin(&mut self) -> Result<(), CompileDbError> {
+        if self.format == OutputFormat::Json {
+            self.inner.write_all(b"[")?;
+        }
+        Ok(())
+    }
+
+    /// Append a single entry to the array
+    pub fn write_entry(&mut self, cmd: &CompileCommand) -> Result<(), CompileDbError> {
+        if self.format == OutputFormat::Jsonl {
+            serde_json::to_writer(&mut self.inner, cmd)?;
+            self.inner.write_all(b"\n")?;
+            return self.entry_written();
+        }
+
+        let separator: &[u8] = if self.entries_written == 0 {
+            b"\n"
+        } else {
+            b",\n"
+        };
+        self.inner.write_all(separator)?;
+
+        // Nest the pretty-printed entry one level inside the array. JSON
+        // strings never contain raw newlines, so indenting by line is safe.
+        let json = serde_json::to_string_pretty(cmd)?;
+        for (i, line) in json.lines().enumerate() {
+            if i > 0 {
+                self.inner.write_all(b"\n")?;
+            }
+            self.inner.write_all(b"  ")?;
+            self.inner.write_all(line.as_bytes())?;
+        }
+        self.entry_written()
+    }
+
+    fn entry_written(&mut self) -> Result<(), CompileDbError> {
+        self.entries_written += 1;
+        if self.flush_each_entry {
+            self.inner.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Close the JSON array and flush the underlying writer
+    pub fn finish(&mut self) -> Result<(), CompileDbError> {
+        if self.format == OutputFormat::Json {
+            if self.entries_written > 0 {
+                self.inner.write_all(b"\n")?;
+            }
+            self.inner.write_all(b"]")?;
+        }
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    /// Number of entries written so far
+    pub fn entries_written(&self) -> usize {
+        self.entries_written
+    }
+
+    /// Consume the writer, returning the underlying output
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(file: &str) -> CompileCommand {
+        CompileCommand {
+            directory: String::from("/tmp"),
+            file: String::from(file),
+            command: None,
+            arguments: Some(vec![
+                String::from("gcc"),
+                String::from("-c"),
+                String::from(file),
+            ]),
+            output: None,
+        }
+    }
+
+    #[test]
+    fn test_streaming_matches_buffered() {
+        for count in [0, 1, 3] {
+            let commands: Vec<_> = (0..count).map(|i| entry(&format!("{i}.c"))).collect();
+
+            let mut writer = StreamingJsonWriter::new(Vec::new());
+            writer.begin().unwrap();
+            for cmd in &commands {
+                writer.write_entry(cmd).unwrap();
+            }
+            writer.finish().unwrap();
+            assert_eq!(writer.entries_written(), count);
+
+            let buffered = serde_json::to_vec_pretty(&commands).unwrap();
+            assert_eq!(writer.into_inner(), buffered);
+        }
+    }
+
+    #[test]
+    fn test_jsonl_round_trip() {
+        let commands = vec![entry("a.c"), entry("b.c")];
+
+        let mut writer = StreamingJsonWriter::with_format(Vec::new(), OutputFormat::Jsonl);
+        writer.begin().unwrap();
+        for cmd in &commands {
+            writer.write_entry(cmd).unwrap();
+        }
+        writer.finish().unwrap();
+        let streamed = writer.into_inner();
+
+        let mut buffered = Vec::new();
+        crate::write_commands(&mut buffered, &commands, OutputFormat::Jsonl).unwrap();
+        assert_eq!(streamed, buffered);
+        assert_eq!(String::from_utf8_lossy(&streamed).lines().count(), 2);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("compile_commands.jsonl");
+        std::fs::write(&path, &streamed).unwrap();
+        assert_eq!(crate::load_database(&path).unwrap(), commands);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::parser::Parser;
+use crate::{
+    CompileCommand, CompileDbError, Config, DEFAULT_REGEX_COMPILE, DEFAULT_REGEX_FILE, expand_tilde,
+};
+use regex::Regex;
+use std::{
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tracing::{debug, info, info_span};
+
+/// Xcode also runs the Swift compiler, which the default `regex_compile` omits
+const XCODE_SWIFT_COMPILE_REGEX: &str = r"(?:[^/]*/)*(swiftc|swift-frontend)(?:\s|$)";
+
+/// The source after `-c`, possibly after other flags (`swift-frontend -c
+/// -primary-file a.swift`) and single-quoted when it contains a space
+const XCODE_FILE_REGEX: &str = r"\s-c\s+(?:.*?\s)?(?:'([^']+\.(?:c|cpp|cc|cxx|c\+\+|s|m|mm|swift))'|(\S+\.(?:c|cpp|cc|cxx|c\+\+|s|m|mm|swift)))(?:\s|$)";
+
+/// Kind of build step announced by an `xcodebuild` directive line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Directive {
+    CompileC,
+    CompileCXX,
+    CompileSwift,
+    Ld,
+}
+
+/// A directive whose command block has not been seen yet
+#[derive(Debug)]
+struct PendingStep {
+    directive: Directive,
+    file: Option<String>,
+    output: Option<String>,
+    directory: Option<PathBuf>,
+}
+
+/// Parser for `xcodebuild -verbose` output.
+///
+/// Each build step starts with an unindented directive line such as
+/// `CompileC <object> <source> normal x86_64 objective-c ...`, followed by
+/// indented `cd`, `export` and finally the compiler invocation itself. The
+/// invocation goes through the regular make-output [`Parser`] in the step's
+/// directory, so every filter and output option applies. Unless custom
+/// regexes are configured, `swiftc` is recognized and sources are found after
+/// `-c` in any of the step's languages.
+pub struct XcodeParser {
+    directive_regex: Regex,
+    parser: Parser,
+    pending: Option<PendingStep>,
+    working_dir: PathBuf,
+}
+
+impl XcodeParser {
+    pub fn new(config: &Config) -> Result<Self, CompileDbError> {
+        let mut config = config.clone();
+        if config.regex_compile == DEFAULT_REGEX_COMPILE {
+            config.regex_compile = format!("{DEFAULT_REGEX_COMPILE}|{XCODE_SWIFT_COMPILE_REGEX}");
+        }
+        if config.regex_file == DEFAULT_REGEX_FILE {
+            config.regex_file = XCODE_FILE_REGEX.to_string();
+        }
+
+        let working_dir = if !config.build_dir.as_os_str().is_empty() {
+            expand_tilde(&config.build_dir)
+        } else {
+            std::env::current_dir().map_err(CompileDbError::Io)?
+        };
+
+        Ok(Self {
+            directive_regex: Regex::new(r"^(CompileC|CompileCXX|CompileSwift|Ld)\s+(.*)$").unwrap(),
+            parser: Parser::new(&config)?,
+            pending: None,
+            working_dir,
+        })
+    }
+
+    /// Parse a single line of `xcodebuild` output
+    pub fn parse_line(
+        &mut self,
+        line: &str,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        // Directive lines are not indented; anything else ends the current step
+        if !line.starts_with(char::is_whitespace) {
+            self.pending = self.parse_directive(line.trim());
+            return Ok(Vec::new());
+        }
+
+        let Some(pending) = self.pending.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("export ") {
+            return Ok(Vec::new());
+        }
+        if let Some(dir) = line.strip_prefix("cd ") {
+            pending.directory = Some(PathBuf::from(unescape(dir.trim())));
+            return Ok(Vec::new());
+        }
+
+        // The first other indented line is the tool invocation
+        let pending = self.pending.take().unwrap();
+        if pending.directive == Directive::Ld {
+            debug!("Skipping link step: {line}");
+            return Ok(Vec::new());
+        }
+        if pending.file.is_none() {
+            return Ok(Vec::new());
+        }
+
+        // xcodebuild escapes spaces with a backslash; re-quote the words for
+        // the shell-style splitting of the make-output parser
+        let words = split_escaped(line);
+        let invocation =
+            shlex::try_join(words.iter().map(String::as_str)).unwrap_or_else(|_| words.join(" "));
+        let directory = pending
+            .directory
+            .unwrap_or_else(|| self.working_dir.clone());
+        self.parser.set_working_dir(directory);
+
+        let mut commands = self.parser.try_parse_line(&invocation, config)?;
+        for cmd in &mut commands {
+            cmd.output = pending.output.clone();
+        }
+        Ok(commands)
+    }
+
+    /// Parse an `xcodebuild` log file and extract compilation commands
+    pub fn parse_file(
+        &mut self,
+        path: &Path,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        let _span = info_span!("parse_xcode_file", path = ?path).entered();
+        let file = std::fs::File::open(path)?;
+        self.parse_reader(BufReader::new(file), config)
+    }
+
+    /// Parse `xcodebuild` output from any buffered reader
+    pub fn parse_reader<R: BufRead>(
+        &mut self,
+        reader: R,
+        config: &Config,
+    ) -> Result<Vec<CompileCommand>, CompileDbError> {
+        let mut commands = Vec::new();
+        for line in reader.lines() {
+            let line = line.map_err(CompileDbError::Io)?;
+            commands.extend(self.parse_line(&line, config)?);
+        }
+        info!("Found {} compilation commands", commands.len());
+        Ok(commands)
+    }
+
+    fn parse_directive(&self, line: &str) -> Option<PendingStep> {
+        let caps = self.directive_regex.captures(line)?;
+        let directive = match &caps[1] {
+            "CompileC" => Directive::CompileC,
+            "CompileCXX" => Directive::CompileCXX,
+            "CompileSwift" => Directive::CompileSwift,
+            _ => Directive::Ld,
+        };
+
+        // Drop the trailing "(in target 'X' from project 'Y')" annotation
+        let rest = caps[2].split(" (in target").next().unwrap_or_default();
+        let tokens = split_escaped(rest);
+
+        let (output, file) = match directive {
+            // CompileC <object> <source> <variant> <arch> <language> <compiler>
+            Directive::CompileC | Directive::CompileCXX => {
+                (tokens.first().cloned(), tokens.get(1).cloned())
+            }
+            // CompileSwift <variant> <arch> <source>
+            Directive::CompileSwift => (None, tokens.last().cloned()),
+            // Ld <output> <variant> <arch>
+            Directive::Ld => (tokens.first().cloned(), None),
+        };
+
+        debug!("Found xcodebuild {directive:?} step for {file:?}");
+        Some(PendingStep {
+            directive,
+            file,
+            output,
+            directory: None,
+        })
+    }
+}
+
+/// Run `xcodebuild` with the given arguments and parse its output
+pub fn run_xcodebuild(
+    args: &[String],
+    config: &Config,
+) -> Result<Vec<CompileCommand>, CompileDbError> {
+    info!("Executing xcodebuild with arguments: {:?}", args);
+
+    let mut child = Command::new("xcodebuild")
+        .args(args)
+        .current_dir(expand_tilde(&config.build_dir))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+
+    let stdout = child.stdout.take().ok_or_else(|| {
+        CompileDbError::MakeError("Failed to capture xcodebuild stdout".to_string())
+    })?;
+
+    let mut parser = XcodeParser::new(config)?;
+    let commands = parser.parse_reader(BufReader::new(stdout), config)?;
+
+    let status = child
+        .wait()
+        .map_err(|e| CompileDbError::MakeError(e.to_string()))?;
+    if !status.success() {
+        return Err(CompileDbError::MakeError(
+            "xcodebuild command failed".to_string(),
+        ));
+    }
+
+    Ok(commands)
+}
+
+/// Split on whitespace, keeping backslash-escaped spaces inside a token
+fn split_escaped(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn unescape(path: &str) -> String {
+    split_escaped(path).join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XCODE_LOG: &str = r"CompileC /tmp/DerivedData/App.build/Objects-normal/x86_64/main.o /src/App/main.m normal x86_64 objective-c com.apple.compilers.llvm.clang.1_0.compiler (in target 'App' from project 'App')
+    cd /src/App
+    export LANG=en_US.US-ASCII
+    /usr/bin/clang -x objective-c -arch x86_64 -c /src/App/main.m -o /tmp/DerivedData/App.build/Objects-normal/x86_64/main.o
+
+CompileSwift normal x86_64 /src/App/My\ View.swift (in target 'App' from project 'App')
+    cd /src/App
+    /usr/bin/swiftc -frontend -c /src/App/My\ View.swift
+
+Ld /tmp/DerivedData/App normal x86_64 (in target 'App' from project 'App')
+    cd /src/App
+    /usr/bin/clang -arch x86_64 -o /tmp/DerivedData/App main.o
+";
+
+    #[test]
+    fn test_parse_xcode_log() {
+        let config = Config {
+            no_strict: true,
+            ..Config::default()
+        };
+        let mut parser = XcodeParser::new(&config).unwrap();
+
+        let commands = parser
+            .parse_reader(std::io::Cursor::new(XCODE_LOG), &config)
+            .unwrap();
+        assert_eq!(commands.len(), 2);
+
+        let objc = &commands[0];
+        assert_eq!(objc.directory, "/src/App");
+        assert_eq!(objc.file, "main.m");
+        assert_eq!(
+            objc.output.as_deref(),
+            Some("/tmp/DerivedData/App.build/Objects-normal/x86_64/main.o")
+        );
+        assert_eq!(objc.arguments.as_ref().unwrap()[0], "/usr/bin/clang");
+
+        let swift = &commands[1];
+        assert_eq!(swift.file, "My View.swift");
+        assert_eq!(
+            swift.arguments.as_ref().unwrap().last().unwrap(),
+            "My View.swift"
+        );
+    }
+
+    #[test]
+    fn test_xcode_log_uses_parser_options() {
+        let config = Config {
+            no_strict: true,
+            command_style: true,
+            include_patterns: vec![String::from(r"\.swift$")],
+            ..Config::default()
+        };
+        let mut parser = XcodeParser::new(&config).unwrap();
+
+        let commands = parser
+            .parse_reader(std::io::Cursor::new(XCODE_LOG), &config)
+            .unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            commands[0].command.as_deref(),
+            Some("/usr/bin/swiftc -frontend -c 'My View.swift'")
+        );
+    }
+}
@@ -0,0 +1,495 @@
+use assert_fs::prelude::*;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Run the compiledb binary in `dir`, feeding `input` on stdin
+fn run_compiledb(dir: &std::path::Path, args: &[&str], input: &str) -> std::process::Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_compiledb"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("COMPILEDB_BUILD_DIR")
+        .env_remove("CC")
+        .env_remove("CXX")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_dry_run_from_parse_file() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let log = temp.child("build.log");
+    log.write_str("gcc -c a.c -o a.o\ngcc -c b.c -o b.o\n")
+        .unwrap();
+
+    let result = run_compiledb(
+        temp.path(),
+        &["--no-strict", "--dry-run", "--parse", "build.log"],
+        "",
+    );
+    assert!(result.status.success());
+
+    let printed: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(printed.as_array().unwrap().len(), 2);
+    temp.child("compile_commands.json")
+        .assert(predicates::path::missing());
+}
+
+#[test]
+fn test_build_dir_from_environment() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let src = temp.child("src");
+    src.child("a.c").touch().unwrap();
+
+    // The environment variable wins over --build-dir
+    let mut child = Command::new(env!("CARGO_BIN_EXE_compiledb"))
+        .args(["--dry-run", "--build-dir", "elsewhere"])
+        .current_dir(temp.path())
+        .env("COMPILEDB_BUILD_DIR", src.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"gcc -c a.c -o a.o\n")
+        .unwrap();
+    let result = child.wait_with_output().unwrap();
+    assert!(result.status.success());
+
+    let printed: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(printed[0]["directory"], src.path().to_str().unwrap());
+}
+
+#[test]
+fn test_config_file_with_cli_override() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("compiledb.toml")
+        .write_str("no_strict = true\nsort = true\noutput_file = \"from_file.json\"\n")
+        .unwrap();
+
+    let result = run_compiledb(
+        temp.path(),
+        &[
+            "--dry-run",
+            "--config-file",
+            "compiledb.toml",
+            "-o",
+            "from_cli.json",
+        ],
+        "gcc -c b.c -o b.o\ngcc -c a.c -o a.o\n",
+    );
+    assert!(result.status.success());
+
+    // Settings from the file apply, the output flag overrides the file
+    let printed: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(printed[0]["file"], "a.c");
+    assert_eq!(printed[1]["file"], "b.c");
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Would write 2 entries to from_cli.json"));
+}
+
+#[test]
+fn test_save_and_load_config() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let log = "gcc -c b.c -o b.o\ngcc -c a.c -o a.o\n";
+
+    let result = run_compiledb(
+        temp.path(),
+        &[
+            "--no-strict",
+            "--sort",
+            "--save-config",
+            "saved.json",
+            "-o",
+            "saved_out.json",
+        ],
+        log,
+    );
+    assert!(result.status.success());
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(temp.child("saved.json").path()).unwrap())
+            .unwrap();
+    assert_eq!(saved["no_strict"], true);
+    assert_eq!(saved["output_file"], "saved_out.json");
+
+    // Loaded settings apply, flags still take precedence
+    let result = run_compiledb(
+        temp.path(),
+        &[
+            "--load-config",
+            "saved.json",
+            "--dry-run",
+            "-o",
+            "other.json",
+        ],
+        log,
+    );
+    assert!(result.status.success());
+    let printed: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(printed[0]["file"], "a.c");
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Would write 2 entries to other.json"));
+}
+
+#[test]
+fn test_compiler_from_cc_environment() {
+    let temp = assert_fs::TempDir::new().unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_compiledb"))
+        .args(["--no-strict", "--dry-run"])
+        .current_dir(temp.path())
+        .env("CC", "mycc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"mycc -c a.c -o a.o\n")
+        .unwrap();
+    let result = child.wait_with_output().unwrap();
+    assert!(result.status.success());
+
+    let printed: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(printed[0]["arguments"][0], "mycc");
+}
+
+#[test]
+fn test_progress_on_large_log() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let log: String = (0..25_000)
+        .map(|i| format!("gcc -c src{i}.c -o src{i}.o\n"))
+        .collect();
+
+    // stderr is a pipe here, so progress has to be forced
+    let result = run_compiledb(temp.path(), &["--no-strict", "--progress=always"], &log);
+    assert!(result.status.success());
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Processed 10000 lines, found 10000 compile commands"));
+    assert!(stderr.contains("Processed 20000 lines, found 20000 compile commands"));
+
+    let result = run_compiledb(temp.path(), &["--no-strict", "--progress"], &log);
+    assert!(result.status.success());
+    assert!(!String::from_utf8_lossy(&result.stderr).contains("Processed"));
+
+    // progress from a config file is subject to the same terminal check
+    temp.child("compiledb.toml")
+        .write_str("progress = true\n")
+        .unwrap();
+    let result = run_compiledb(
+        temp.path(),
+        &["--no-strict", "--config-file", "compiledb.toml"],
+        &log,
+    );
+    assert!(result.status.success());
+    assert!(!String::from_utf8_lossy(&result.stderr).contains("Processed"));
+}
+
+#[test]
+fn test_progress_before_subcommand() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("Makefile")
+        .write_str("all:\n\tgcc -c a.c -o a.o\n")
+        .unwrap();
+
+    // A bare --progress must not take the subcommand as its value
+    let result = run_compiledb(
+        temp.path(),
+        &["--no-strict", "--no-build", "--progress", "make"],
+        "",
+    );
+    assert!(
+        result.status.success(),
+        "{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+    let database = std::fs::read_to_string(temp.child("compile_commands.json").path()).unwrap();
+    assert!(database.contains("\"file\": \"a.c\""));
+}
+
+#[test]
+fn test_dry_run_leaves_output_unchanged() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let output = temp.child("compile_commands.json");
+    output.write_str("[]").unwrap();
+
+    let result = run_compiledb(
+        temp.path(),
+        &["--no-strict", "--dry-run"],
+        "gcc -c a.c -o a.o\n",
+    );
+    assert!(result.status.success());
+
+    // The database goes to stdout, the summary to stderr
+    let printed: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(printed.as_array().unwrap().len(), 1);
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Would write 1 entries to compile_commands.json"));
+    assert!(stderr.contains("added:"));
+    output.assert("[]");
+}
+
+#[test]
+fn test_watch_regenerates_on_log_change() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let log = temp.child("build.log");
+    log.write_str("gcc -c a.c -o a.o\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_compiledb"))
+        .args(["--no-strict", "--parse", "build.log", "--watch"])
+        .current_dir(temp.path())
+        .env_remove("COMPILEDB_BUILD_DIR")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Poll the output until it holds `expected` entries
+    let output = temp.child("compile_commands.json");
+    let wait_for_entries = |expected: usize| {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while std::time::Instant::now() < deadline {
+            let entries = std::fs::read_to_string(output.path())
+                .ok()
+                .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok())
+                .and_then(|db| db.as_array().map(Vec::len));
+            if entries == Some(expected) {
+                return true;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        false
+    };
+
+    let first = wait_for_entries(1);
+    // Give the watcher time to start before changing the log
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(log.path())
+        .unwrap()
+        .write_all(b"gcc -c b.c -o b.o\n")
+        .unwrap();
+    let regenerated = wait_for_entries(2);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(first);
+    assert!(regenerated);
+}
+
+#[test]
+fn test_parse_xcode_log() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("xcodebuild.log")
+        .write_str(
+            "CompileC /tmp/App.build/main.o /src/App/main.m normal arm64 objective-c com.apple.compilers.llvm.clang.1_0.compiler (in target 'App' from project 'App')
+    cd /src/App
+    export LANG=en_US.US-ASCII
+    /usr/bin/clang -x objective-c -target arm64-apple-ios15.0 -fobjc-arc -c /src/App/main.m -o /tmp/App.build/main.o
+",
+        )
+        .unwrap();
+
+    let result = run_compiledb(
+        temp.path(),
+        &[
+            "--no-strict",
+            "--dry-run",
+            "--log-format",
+            "xcode",
+            "--parse",
+            "xcodebuild.log",
+        ],
+        "",
+    );
+    assert!(result.status.success());
+
+    let printed: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(printed.as_array().unwrap().len(), 1);
+    assert_eq!(printed[0]["directory"], "/src/App");
+    assert_eq!(printed[0]["file"], "main.m");
+    assert_eq!(printed[0]["arguments"][0], "/usr/bin/clang");
+    assert_eq!(printed[0]["output"], "/tmp/App.build/main.o");
+}
+
+#[test]
+fn test_error_on_empty() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let log = "make: Nothing to be done for 'all'.\n";
+
+    let result = run_compiledb(temp.path(), &["--no-strict"], log);
+    assert!(result.status.success());
+
+    let result = run_compiledb(temp.path(), &["--no-strict", "--error-on-empty"], log);
+    assert_eq!(result.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Found 0 compile commands, expected at least 1"));
+
+    // A lower --min-commands does not cancel --error-on-empty
+    let result = run_compiledb(
+        temp.path(),
+        &["--no-strict", "--error-on-empty", "--min-commands", "0"],
+        log,
+    );
+    assert_eq!(result.status.code(), Some(1));
+
+    // A failing run leaves the previous database in place, whether streamed
+    // or buffered for post-processing
+    let output = temp.child("compile_commands.json");
+    let result = run_compiledb(temp.path(), &["--no-strict"], "gcc -c a.c -o a.o\n");
+    assert!(result.status.success());
+    let good = std::fs::read_to_string(output.path()).unwrap();
+
+    let result = run_compiledb(temp.path(), &["--no-strict", "--error-on-empty"], log);
+    assert_eq!(result.status.code(), Some(1));
+    assert_eq!(std::fs::read_to_string(output.path()).unwrap(), good);
+
+    let result = run_compiledb(
+        temp.path(),
+        &["--no-strict", "--error-on-empty", "--sort"],
+        log,
+    );
+    assert_eq!(result.status.code(), Some(1));
+    assert_eq!(std::fs::read_to_string(output.path()).unwrap(), good);
+}
+
+#[test]
+fn test_emit_both() {
+    let temp = assert_fs::TempDir::new().unwrap();
+
+    let result = run_compiledb(
+        temp.path(),
+        &["--no-strict", "--emit-both"],
+        "gcc -DMSG='a b' -c a.c -o a.o\n",
+    );
+    assert!(result.status.success());
+
+    let read = |name: &str| -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(temp.child(name).path()).unwrap()).unwrap()
+    };
+    let arguments = read("compile_commands.json");
+    assert_eq!(arguments[0]["arguments"][1], "-DMSG=a b");
+    assert!(arguments[0].get("command").is_none());
+
+    let command = read("compile_commands.command.json");
+    assert_eq!(command[0]["command"], "gcc '-DMSG=a b' -c a.c -o a.o");
+    assert!(command[0].get("arguments").is_none());
+}
+
+#[test]
+fn test_append() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let files = |output: &std::process::Output| -> Vec<(String, String)> {
+        assert!(output.status.success());
+        let path = temp.child("compile_commands.json");
+        let database: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path.path()).unwrap()).unwrap();
+        database
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                (
+                    entry["file"].as_str().unwrap().to_string(),
+                    entry["arguments"][1].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    };
+
+    // Without an existing database, --append is a normal run
+    let result = run_compiledb(
+        temp.path(),
+        &["--no-strict", "--append"],
+        "gcc -O0 -c a.c -o a.o\ngcc -O0 -c b.c -o b.o\n",
+    );
+    assert_eq!(
+        files(&result),
+        [("a.c".into(), "-O0".into()), ("b.c".into(), "-O0".into())]
+    );
+
+    let result = run_compiledb(
+        temp.path(),
+        &["--no-strict", "--append"],
+        "gcc -O1 -c b.c -o b.o\ngcc -O2 -c b.c -o b.o\ngcc -O2 -c c.c -o c.o\n",
+    );
+    assert_eq!(
+        files(&result),
+        [
+            ("a.c".into(), "-O0".into()),
+            ("b.c".into(), "-O2".into()),
+            ("c.c".into(), "-O2".into())
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_parse_fifo_streams_output() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let fifo = temp.child("build.fifo");
+    assert!(
+        Command::new("mkfifo")
+            .arg(fifo.path())
+            .status()
+            .unwrap()
+            .success()
+    );
+
+    let child = Command::new(env!("CARGO_BIN_EXE_compiledb"))
+        .args(["--no-strict", "--format", "jsonl", "--parse", "build.fifo"])
+        .current_dir(temp.path())
+        .env_remove("COMPILEDB_BUILD_DIR")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Opening the write end blocks until compiledb opens the FIFO
+    let mut writer = std::fs::OpenOptions::new()
+        .write(true)
+        .open(fifo.path())
+        .unwrap();
+    writer.write_all(b"gcc -c a.c -o a.o\n").unwrap();
+
+    // The first entry shows up while the FIFO is still open
+    let output = temp.child("compile_commands.json");
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    let mut streamed = false;
+    while !streamed && std::time::Instant::now() < deadline {
+        streamed = std::fs::read_to_string(output.path()).is_ok_and(|db| db.contains("a.c"));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    writer.write_all(b"gcc -c b.c -o b.o\n").unwrap();
+    drop(writer);
+    let result = child.wait_with_output().unwrap();
+    assert!(streamed);
+    assert!(result.status.success());
+
+    let database = std::fs::read_to_string(output.path()).unwrap();
+    let files: Vec<String> = database
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["file"].to_string())
+        .collect();
+    assert_eq!(files, ["\"a.c\"", "\"b.c\""]);
+}